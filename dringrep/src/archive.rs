@@ -0,0 +1,143 @@
+/*
+--search-archives: .tar, .tar.gz / .tgz and .zip files are opened and every member is
+searched as a file of its own, named `bundle.zip!/src/main.rs`.
+
+Members go through the same checks as files on disk (--file-extension, --no-hidden,
+--max-filesize, binary detection) and the same search, so they print and count like any
+other file. Archives inside archives are not opened.
+*/
+
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc;
+
+use flate2::read::GzDecoder;
+
+use crate::utils::{extension_matches, record_skip, search_contents};
+use crate::{Config, Entry, FileResult, SkipReason, Stats};
+
+// members are read into memory whole; larger ones are skipped unless --max-filesize says
+// otherwise
+const MAX_MEMBER_SIZE: u64 = 512 * 1024 * 1024;
+
+#[derive(Clone, Copy)]
+pub enum ArchiveKind {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+// by name, like the extension filter; None unless --search-archives is on
+pub fn archive_kind(config: &Config, path: &Path) -> Option<ArchiveKind> {
+    if !config.search_archives {
+        return None;
+    }
+    let name = path.file_name()?.to_string_lossy().to_ascii_lowercase();
+    if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(ArchiveKind::TarGz)
+    } else if name.ends_with(".tar") {
+        Some(ArchiveKind::Tar)
+    } else if name.ends_with(".zip") {
+        Some(ArchiveKind::Zip)
+    } else {
+        None
+    }
+}
+
+// every member's result is sent on its own; an unreadable archive is one error skip
+pub fn search_archive(
+    config: &Config,
+    path: &Path,
+    kind: ArchiveKind,
+    tx: &mpsc::Sender<FileResult>,
+) {
+    let mut emit = |member: &Path, size: u64, reader: &mut dyn Read| {
+        let result = search_member(config, path, member, size, reader);
+        if let Err(e) = tx.send(result) {
+            eprintln!("failed to send result back to main: {:?}", e);
+        }
+    };
+
+    let read = File::open(path).and_then(|file| match kind {
+        ArchiveKind::Tar => read_tar(file, &mut emit),
+        ArchiveKind::TarGz => read_tar(GzDecoder::new(file), &mut emit),
+        ArchiveKind::Zip => read_zip(file, &mut emit),
+    });
+    if read.is_err() {
+        record_skip(config, path, SkipReason::Error);
+    }
+}
+
+type Emit<'a> = dyn FnMut(&Path, u64, &mut dyn Read) + 'a;
+
+fn read_tar<R: Read>(reader: R, emit: &mut Emit) -> io::Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let member = entry.path()?.into_owned();
+        let size = entry.size();
+        emit(&member, size, &mut entry);
+    }
+    Ok(())
+}
+
+fn read_zip(file: File, emit: &mut Emit) -> io::Result<()> {
+    let mut archive = zip::ZipArchive::new(BufReader::new(file)).map_err(io::Error::other)?;
+    for i in 0..archive.len() {
+        let mut member = archive.by_index(i).map_err(io::Error::other)?;
+        if !member.is_file() {
+            continue;
+        }
+        let name = PathBuf::from(member.name());
+        let size = member.size();
+        emit(&name, size, &mut member);
+    }
+    Ok(())
+}
+
+fn search_member(
+    config: &Config,
+    archive: &Path,
+    member: &Path,
+    size: u64,
+    reader: &mut dyn Read,
+) -> FileResult {
+    // tar members often start with `./`, which the name leaves out
+    let member: PathBuf = member
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect();
+    let path = PathBuf::from(format!("{}!/{}", archive.display(), member.display()));
+    if let Some(stats) = &config.stats {
+        Stats::add(&stats.files_considered, 1);
+    }
+
+    let hidden = member
+        .components()
+        .any(|c| c.as_os_str().to_string_lossy().starts_with('.'));
+    if !config.hidden && hidden {
+        return record_skip(config, &path, SkipReason::Hidden);
+    }
+    if !extension_matches(&Entry::new(path.clone(), 0), config) {
+        return record_skip(config, &path, SkipReason::Filter);
+    }
+    if config.max_filesize.is_some_and(|max| size > max) {
+        return record_skip(config, &path, SkipReason::TooLarge);
+    }
+
+    // the size in the header is whatever the archive claims, so it only decides what is
+    // skipped; reading stops one byte past the limit, which also bounds zip bombs
+    let limit = config.max_filesize.unwrap_or(MAX_MEMBER_SIZE);
+    let mut bytes = Vec::new();
+    if reader.take(limit + 1).read_to_end(&mut bytes).is_err() {
+        return record_skip(config, &path, SkipReason::Error);
+    }
+    if bytes.len() as u64 > limit {
+        return record_skip(config, &path, SkipReason::TooLarge);
+    }
+    search_contents(config, path, &bytes)
+}
@@ -0,0 +1,282 @@
+use std::io::IsTerminal;
+use std::str::FromStr;
+
+use clap::ValueEnum;
+use colored::{Color, ColoredString, Colorize};
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    // auto only colours a terminal, and NO_COLOR (https://no-color.org) turns it off
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                let no_color = std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+                !no_color && std::io::stdout().is_terminal()
+            }
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub underline: bool,
+    pub italic: bool,
+}
+
+impl Style {
+    pub fn paint(&self, text: &str) -> ColoredString {
+        let mut out = text.normal();
+        if let Some(fg) = self.fg {
+            out = out.color(fg);
+        }
+        if let Some(bg) = self.bg {
+            out = out.on_color(bg);
+        }
+        if self.bold {
+            out = out.bold();
+        }
+        if self.underline {
+            out = out.underline();
+        }
+        if self.italic {
+            out = out.italic();
+        }
+        out
+    }
+}
+
+// highest N accepted in `match.N`
+const MAX_MATCH_STYLE: usize = 255;
+
+// one style per output element, changed with --colors 'match:fg:yellow'
+#[derive(Clone)]
+pub struct ColorSpecs {
+    pub path: Style,
+    pub line: Style,
+    pub matched: Style,
+    // matches of the 2nd, 3rd, ... pattern, cycled when there are more patterns than styles
+    pub other_matches: Vec<Style>,
+    pub separator: Style,
+}
+
+impl Default for ColorSpecs {
+    fn default() -> Self {
+        ColorSpecs {
+            path: Style {
+                fg: Some(Color::Green),
+                ..Style::default()
+            },
+            line: Style::default(),
+            matched: Style {
+                fg: Some(Color::Red),
+                bold: true,
+                underline: true,
+                ..Style::default()
+            },
+            other_matches: [
+                Color::Green,
+                Color::Yellow,
+                Color::Blue,
+                Color::Magenta,
+                Color::Cyan,
+            ]
+            .into_iter()
+            .map(|fg| Style {
+                fg: Some(fg),
+                bold: true,
+                underline: true,
+                ..Style::default()
+            })
+            .collect(),
+            separator: Style::default(),
+        }
+    }
+}
+
+impl ColorSpecs {
+    pub fn for_pattern(&self, index: usize) -> &Style {
+        if index == 0 || self.other_matches.is_empty() {
+            &self.matched
+        } else {
+            &self.other_matches[(index - 1) % self.other_matches.len()]
+        }
+    }
+
+    // specs are applied in order, so a later spec for the same element wins
+    pub fn apply(&mut self, spec: &str) -> Result<(), String> {
+        let parts: Vec<&str> = spec.split(':').collect();
+
+        let style = match parts[0] {
+            "path" => &mut self.path,
+            "line" => &mut self.line,
+            "match" | "match.0" => &mut self.matched,
+            "separator" | "sep" => &mut self.separator,
+            // match.N styles the matches of pattern N (0-based) with several patterns
+            other if other.starts_with("match.") => {
+                // one style is kept per pattern up to N, so N stays small
+                let index: usize = other["match.".len()..]
+                    .parse()
+                    .ok()
+                    .filter(|&i| i <= MAX_MATCH_STYLE)
+                    .ok_or_else(|| format!("invalid colour target `{}`", other))?;
+                // `match.00` is still the first pattern
+                if index == 0 {
+                    &mut self.matched
+                } else {
+                    if self.other_matches.len() < index {
+                        self.other_matches.resize(index, self.matched.clone());
+                    }
+                    &mut self.other_matches[index - 1]
+                }
+            }
+            other => {
+                return Err(format!(
+                    "unknown colour target `{}` (expected path, line, match, match.N or separator)",
+                    other
+                ));
+            }
+        };
+
+        match parts[1..] {
+            ["none"] => *style = Style::default(),
+            ["fg", value] => style.fg = Some(parse_color(value)?),
+            ["bg", value] => style.bg = Some(parse_color(value)?),
+            ["style", "bold"] => style.bold = true,
+            ["style", "nobold"] => style.bold = false,
+            ["style", "underline"] => style.underline = true,
+            ["style", "nounderline"] => style.underline = false,
+            ["style", "italic"] => style.italic = true,
+            ["style", "noitalic"] => style.italic = false,
+            _ => {
+                return Err(format!(
+                    "invalid colour spec `{}` (expected TARGET:fg:COLOR, TARGET:bg:COLOR, TARGET:style:STYLE or TARGET:none)",
+                    spec
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// accepts colour names ("yellow", "bright blue"), "#rrggbb" and "r,g,b"
+fn parse_color(value: &str) -> Result<Color, String> {
+    let invalid = || format!("invalid colour `{}`", value);
+
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() != 6 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+        return Ok(Color::TrueColor {
+            r: channel(0)?,
+            g: channel(2)?,
+            b: channel(4)?,
+        });
+    }
+
+    if value.contains(',') {
+        let channels: Vec<u8> = value
+            .split(',')
+            .map(|c| c.trim().parse::<u8>().map_err(|_| invalid()))
+            .collect::<Result<_, _>>()?;
+        if let [r, g, b] = channels[..] {
+            return Ok(Color::TrueColor { r, g, b });
+        }
+        return Err(invalid());
+    }
+
+    Color::from_str(value).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fg(specs: &ColorSpecs, pattern: usize) -> Option<Color> {
+        specs.for_pattern(pattern).fg
+    }
+
+    #[test]
+    fn match_n_styles_pattern_n() {
+        let mut specs = ColorSpecs::default();
+        specs.apply("match.0:fg:white").unwrap();
+        specs.apply("match.2:fg:black").unwrap();
+        assert_eq!(fg(&specs, 0), Some(Color::White));
+        assert_eq!(specs.matched.fg, Some(Color::White));
+        assert_eq!(fg(&specs, 1), Some(Color::Green));
+        assert_eq!(fg(&specs, 2), Some(Color::Black));
+
+        specs.apply("match.00:fg:red").unwrap();
+        specs.apply("match.255:fg:red").unwrap();
+        assert_eq!(fg(&specs, 0), Some(Color::Red));
+    }
+
+    #[test]
+    fn match_n_past_the_defaults_grows_the_list() {
+        let mut specs = ColorSpecs::default();
+        specs.apply("match.8:bg:#102030").unwrap();
+        assert_eq!(specs.other_matches.len(), 8);
+        assert_eq!(
+            specs.for_pattern(8).bg,
+            Some(Color::TrueColor {
+                r: 0x10,
+                g: 0x20,
+                b: 0x30
+            })
+        );
+        // the new styles in between look like the first pattern's
+        assert_eq!(fg(&specs, 6), specs.matched.fg);
+    }
+
+    #[test]
+    fn styles_cycle_when_patterns_outnumber_them() {
+        let specs = ColorSpecs::default();
+        let n = specs.other_matches.len();
+        assert_eq!(fg(&specs, 1), fg(&specs, 1 + n));
+        assert_eq!(fg(&specs, n), fg(&specs, 2 * n));
+    }
+
+    #[test]
+    fn invalid_specs_are_errors() {
+        let mut specs = ColorSpecs::default();
+        for bad in [
+            "match.x:fg:red",
+            "match.-1:fg:red",
+            "match.:fg:red",
+            "matches:fg:red",
+            "match:fg",
+            "match:fg:nocolour",
+            "match:fg:#12345",
+            "match:fg:#aébbb",
+            "match:fg:1,2",
+            "match:fg:1,2,300",
+            "match:style:blink",
+            "match.256:fg:red",
+            "match.99999999999999:fg:red",
+        ] {
+            assert!(specs.apply(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn colour_forms() {
+        assert_eq!(parse_color("yellow"), Ok(Color::Yellow));
+        assert_eq!(parse_color("bright blue"), Ok(Color::BrightBlue));
+        assert_eq!(
+            parse_color("1, 2,3"),
+            Ok(Color::TrueColor { r: 1, g: 2, b: 3 })
+        );
+    }
+}
@@ -0,0 +1,428 @@
+/*
+Boolean queries over several patterns, e.g.
+
+    (unsafe & unwrap) & !"SAFETY"
+    TODO | FIXME | /XXX+/
+
+`&` binds tighter than `|`, `!` negates, parentheses group. Bare words and "quoted strings"
+are literals, /slashes/ make a regex leaf. All literal leaves are compiled into one
+Aho-Corasick automaton, so a line is scanned once for every literal in the expression.
+*/
+
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder};
+use regex::{Regex, RegexBuilder};
+
+use crate::{Matcher, Span};
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+    Literal(String),
+    Regex(String),
+}
+
+pub enum Node {
+    // index into the shared Aho-Corasick automaton
+    Literal(usize),
+    // index into QueryExpr::regexes
+    Regex(usize),
+    And(Vec<Node>),
+    Or(Vec<Node>),
+    Not(Box<Node>),
+}
+
+pub struct QueryExpr {
+    root: Node,
+    literals: AhoCorasick,
+    literal_count: usize,
+    regexes: Vec<Regex>,
+    // leaves that are not under a `!`, the only ones worth highlighting
+    positive: Vec<bool>,
+    // source of every leaf, literals first, then regexes
+    pub leaf_names: Vec<String>,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '&' => {
+                chars.next();
+                tokens.push(Token::And);
+            }
+            '|' => {
+                chars.next();
+                tokens.push(Token::Or);
+            }
+            '!' => {
+                chars.next();
+                tokens.push(Token::Not);
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '"' | '/' => {
+                let quote = c;
+                chars.next();
+                let mut value = String::new();
+                let mut closed = false;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' if quote == '"' => match chars.next() {
+                            Some(escaped) => value.push(escaped),
+                            None => break,
+                        },
+                        // \/ inside a regex is a literal slash, other escapes are the regex's
+                        '\\' if chars.peek() == Some(&'/') => {
+                            value.push('/');
+                            chars.next();
+                        }
+                        c if c == quote => {
+                            closed = true;
+                            break;
+                        }
+                        c => value.push(c),
+                    }
+                }
+                if !closed {
+                    return Err(format!("unterminated {} in expression", quote));
+                }
+                tokens.push(if quote == '"' {
+                    Token::Literal(value)
+                } else {
+                    Token::Regex(value)
+                });
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "&|!()\"".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Literal(word));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    literals: Vec<String>,
+    regexes: Vec<String>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&Token> {
+        self.pos += 1;
+        self.tokens.get(self.pos - 1)
+    }
+
+    // or := and ('|' and)*
+    fn parse_or(&mut self) -> Result<Node, String> {
+        let mut nodes = vec![self.parse_and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            nodes.push(self.parse_and()?);
+        }
+        Ok(if nodes.len() == 1 {
+            nodes.pop().unwrap()
+        } else {
+            Node::Or(nodes)
+        })
+    }
+
+    // and := unary ('&' unary)*
+    fn parse_and(&mut self) -> Result<Node, String> {
+        let mut nodes = vec![self.parse_unary()?];
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            nodes.push(self.parse_unary()?);
+        }
+        Ok(if nodes.len() == 1 {
+            nodes.pop().unwrap()
+        } else {
+            Node::And(nodes)
+        })
+    }
+
+    // unary := '!' unary | '(' or ')' | literal | regex
+    fn parse_unary(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some(Token::Not) => Ok(Node::Not(Box::new(self.parse_unary()?))),
+            Some(Token::Open) => {
+                let node = self.parse_or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(node),
+                    _ => Err("missing `)` in expression".to_string()),
+                }
+            }
+            Some(Token::Literal(s)) => {
+                let s = s.clone();
+                // the same literal twice is one leaf
+                let index = match self.literals.iter().position(|l| *l == s) {
+                    Some(i) => i,
+                    None => {
+                        self.literals.push(s);
+                        self.literals.len() - 1
+                    }
+                };
+                Ok(Node::Literal(index))
+            }
+            Some(Token::Regex(s)) => {
+                let s = s.clone();
+                self.regexes.push(s);
+                Ok(Node::Regex(self.regexes.len() - 1))
+            }
+            Some(t) => Err(format!("unexpected {:?} in expression", t)),
+            None => Err("expression ends too early".to_string()),
+        }
+    }
+}
+
+impl QueryExpr {
+    pub fn parse(input: &str, ignore_case: bool) -> Result<QueryExpr, String> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            pos: 0,
+            literals: Vec::new(),
+            regexes: Vec::new(),
+        };
+        let root = parser.parse_or()?;
+        if let Some(t) = parser.peek() {
+            return Err(format!("unexpected {:?} in expression", t));
+        }
+        if parser.literals.iter().any(String::is_empty) {
+            return Err("empty literal in expression".to_string());
+        }
+
+        let literals = AhoCorasickBuilder::new()
+            .ascii_case_insensitive(ignore_case)
+            .build(&parser.literals)
+            .map_err(|e| e.to_string())?;
+        let regexes = parser
+            .regexes
+            .iter()
+            .map(|r| {
+                RegexBuilder::new(r)
+                    .case_insensitive(ignore_case)
+                    .build()
+                    .map_err(|e| format!("invalid regex `{}` in expression: {}", r, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let literal_count = parser.literals.len();
+        let mut positive = vec![false; literal_count + regexes.len()];
+        mark_positive(&root, true, literal_count, &mut positive);
+
+        let mut leaf_names = parser.literals;
+        leaf_names.extend(parser.regexes.iter().map(|r| format!("/{}/", r)));
+
+        Ok(QueryExpr {
+            root,
+            literals,
+            literal_count,
+            regexes,
+            positive,
+            leaf_names,
+        })
+    }
+
+    // which literal leaves occur in `text`, in one pass of the automaton
+    fn literal_hits(&self, text: &str, hits: &mut [bool]) {
+        for m in self.literals.find_overlapping_iter(text) {
+            hits[m.pattern().as_usize()] = true;
+        }
+    }
+
+    fn eval(&self, node: &Node, literal_hits: &[bool], regex_hit: &dyn Fn(usize) -> bool) -> bool {
+        match node {
+            Node::Literal(i) => literal_hits[*i],
+            Node::Regex(i) => regex_hit(*i),
+            Node::And(nodes) => nodes.iter().all(|n| self.eval(n, literal_hits, regex_hit)),
+            Node::Or(nodes) => nodes.iter().any(|n| self.eval(n, literal_hits, regex_hit)),
+            Node::Not(n) => !self.eval(n, literal_hits, regex_hit),
+        }
+    }
+
+    // --file-scope: every leaf counts as true if it matches anywhere in the file
+    pub fn matches_file(&self, contents: &str) -> bool {
+        let mut hits = vec![false; self.literal_count];
+        self.literal_hits(contents, &mut hits);
+        let regex_hits: Vec<bool> = self
+            .regexes
+            .iter()
+            .map(|re| contents.lines().any(|line| re.is_match(line)))
+            .collect();
+        self.eval(&self.root, &hits, &|i| regex_hits[i])
+    }
+
+    // lines shown for a file that passed --file-scope: any line with a positive leaf
+    pub fn any_positive_leaf(&self, text: &str) -> bool {
+        !self.find_all(text).is_empty()
+    }
+}
+
+fn mark_positive(node: &Node, positive: bool, literal_count: usize, out: &mut [bool]) {
+    match node {
+        Node::Literal(i) => out[*i] |= positive,
+        Node::Regex(i) => out[literal_count + i] |= positive,
+        Node::And(nodes) | Node::Or(nodes) => {
+            for n in nodes {
+                mark_positive(n, positive, literal_count, out);
+            }
+        }
+        Node::Not(n) => mark_positive(n, !positive, literal_count, out),
+    }
+}
+
+impl Matcher for QueryExpr {
+    fn matches_query(&self, text: &str) -> bool {
+        let mut hits = vec![false; self.literal_count];
+        self.literal_hits(text, &mut hits);
+        // regex leaves are only run if the tree actually reaches them
+        self.eval(&self.root, &hits, &|i| self.regexes[i].is_match(text))
+    }
+
+    fn find_all(&self, text: &str) -> Vec<Span> {
+        let mut spans: Vec<Span> = self
+            .literals
+            .find_overlapping_iter(text)
+            .filter(|m| self.positive[m.pattern().as_usize()])
+            .map(|m| Span {
+                start: m.start(),
+                end: m.end(),
+                pattern: m.pattern().as_usize(),
+            })
+            .collect();
+        for (i, re) in self.regexes.iter().enumerate() {
+            let leaf = self.literal_count + i;
+            if !self.positive[leaf] {
+                continue;
+            }
+            spans.extend(re.find_iter(text).map(|m| Span {
+                start: m.start(),
+                end: m.end(),
+                pattern: leaf,
+            }));
+        }
+        spans.sort_by_key(|s| (s.start, s.pattern));
+        spans
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expr(input: &str) -> QueryExpr {
+        QueryExpr::parse(input, false).unwrap()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let e = expr("a & b | c");
+        assert!(e.matches_query("a b"));
+        assert!(e.matches_query("c"));
+        assert!(!e.matches_query("a"));
+
+        let e = expr("a & (b | c)");
+        assert!(!e.matches_query("c"));
+        assert!(e.matches_query("a c"));
+    }
+
+    #[test]
+    fn not_applies_to_the_next_term() {
+        let e = expr("!a & b");
+        assert!(e.matches_query("b"));
+        assert!(!e.matches_query("a b"));
+
+        let e = expr("!(a | b)");
+        assert!(e.matches_query("c"));
+        assert!(!e.matches_query("b"));
+        assert!(expr("!!a").matches_query("a"));
+    }
+
+    #[test]
+    fn quoted_literals_and_regex_leaves() {
+        let e = expr(r#""a & b" | /x\d+/"#);
+        assert!(e.matches_query("1 a & b 2"));
+        assert!(!e.matches_query("a b"));
+        assert!(e.matches_query("x42"));
+
+        assert!(expr(r#""say \"hi\"""#).matches_query(r#"they say "hi""#));
+        assert!(expr(r"/a\/b/").matches_query("a/b"));
+    }
+
+    #[test]
+    fn unbalanced_parentheses_are_errors() {
+        for bad in ["(a | b", "a | b)", "((a)", "()", ")(", "a & (b | (c)"] {
+            assert!(QueryExpr::parse(bad, false).is_err(), "{}", bad);
+        }
+        assert!(QueryExpr::parse("((a))", false).is_ok());
+    }
+
+    #[test]
+    fn malformed_expressions_are_errors() {
+        for bad in [
+            "", "a &", "| a", "a & & b", "!", r#""open"#, "/open", r#""""#, "/(/",
+        ] {
+            assert!(QueryExpr::parse(bad, false).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn only_positive_leaves_are_highlighted() {
+        let e = expr(r#"unsafe & !"SAFETY" | /un\w+/"#);
+        let text = "unsafe unwrap SAFETY";
+        let names: Vec<&str> = e
+            .find_all(text)
+            .iter()
+            .map(|s| e.leaf_names[s.pattern].as_str())
+            .collect();
+        assert_eq!(names, ["unsafe", r"/un\w+/", r"/un\w+/"]);
+    }
+
+    #[test]
+    fn repeated_literal_is_one_leaf() {
+        let e = expr("a & a | b");
+        assert_eq!(e.leaf_names, ["a", "b"]);
+    }
+
+    #[test]
+    fn file_scope_combines_leaves_across_lines() {
+        let e = expr("alpha & !beta & /^gam/");
+        assert!(e.matches_file("alpha\ngamma\n"));
+        assert!(!e.matches_file("alpha\ngamma\nbeta\n"));
+        assert!(!e.matches_file("alpha gamma\n"));
+    }
+
+    #[test]
+    fn ignore_case_reaches_literals_and_regexes() {
+        let e = QueryExpr::parse("todo & /fix\\w*/", true).unwrap();
+        assert!(e.matches_query("TODO: FIXME"));
+    }
+}
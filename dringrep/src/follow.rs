@@ -0,0 +1,154 @@
+/*
+--follow on a single file: tail -f style matching for growing logs.
+
+Starts at the end of the file (or at the beginning with --from-start) and keeps reading
+what is appended, searching every completed line as it arrives. Line numbers and byte
+offsets are the file's real ones, so the lines that were there before are counted first.
+
+When the file is truncated, or the path now names a different file (logrotate's
+create / copytruncate), reading starts over at the top of the new contents.
+*/
+
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use crate::{Config, FileResult, LineMatch, print_results, search_chunk, search_json};
+
+// how often to look for new data once the end is reached, same as `tail -f -s 0.25`
+const POLL: Duration = Duration::from_millis(250);
+
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+// where reading continues: the file, how far it has been read, and what was read past the
+// last complete line
+struct Tail {
+    file: File,
+    // file offset of the first byte in `pending`
+    offset: u64,
+    // complete lines seen so far
+    lines: usize,
+    pending: Vec<u8>,
+}
+
+impl Tail {
+    fn open(path: &PathBuf, from_start: bool) -> io::Result<Tail> {
+        let file = File::open(path)?;
+        let mut tail = Tail {
+            file,
+            offset: 0,
+            lines: 0,
+            pending: Vec::new(),
+        };
+        if !from_start {
+            // the lines already there are only counted; a trailing unfinished line is kept,
+            // so it is searched whole once the rest of it is written
+            let mut buf = vec![0; 64 * 1024];
+            loop {
+                let n = tail.file.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                tail.pending.extend_from_slice(&buf[..n]);
+                if let Some(last) = tail.pending.iter().rposition(|&b| b == b'\n') {
+                    tail.lines += tail.pending[..=last]
+                        .iter()
+                        .filter(|&&b| b == b'\n')
+                        .count();
+                    tail.offset += last as u64 + 1;
+                    tail.pending.drain(..=last);
+                }
+            }
+        }
+        Ok(tail)
+    }
+
+    fn reset(&mut self) {
+        self.offset = 0;
+        self.lines = 0;
+        self.pending.clear();
+    }
+
+    // searches the complete lines read so far and returns their matches
+    fn take_lines(&mut self, config: &Config, path: &Path) -> Vec<LineMatch<'static>> {
+        let Some(last) = self.pending.iter().rposition(|&b| b == b'\n') else {
+            return Vec::new();
+        };
+        let block: Vec<u8> = self.pending.drain(..=last).collect();
+        let text = String::from_utf8_lossy(&block);
+
+        let matches = match &config.json_path {
+            Some(query) => {
+                search_json(config, query, path, &text, self.lines, self.offset as usize)
+            }
+            None => search_chunk(config, &text, self.lines, self.offset as usize),
+        };
+        let matches = matches.into_iter().map(LineMatch::into_owned).collect();
+
+        self.lines += block.iter().filter(|&&b| b == b'\n').count();
+        self.offset += block.len() as u64;
+        matches
+    }
+}
+
+pub fn follow_file(config: Arc<Config>, path: PathBuf) -> io::Result<()> {
+    let mut tail = Tail::open(&path, config.from_start)?;
+
+    // printing runs on its own thread, the same printer as a normal search
+    let (tx, rx) = mpsc::channel();
+    let printer = {
+        let config = Arc::clone(&config);
+        thread::spawn(move || print_results(rx, config))
+    };
+
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = tail.file.read(&mut buf)?;
+        if n > 0 {
+            tail.pending.extend_from_slice(&buf[..n]);
+            let matches = tail.take_lines(&config, &path);
+            if !matches.is_empty() && tx.send(FileResult::Match(path.clone(), matches)).is_err() {
+                break;
+            }
+            continue;
+        }
+
+        // at the end: check for rotation before waiting for more
+        // (mid-rotation the name can be missing for a moment; then just wait)
+        if let Ok(current) = fs::metadata(&path) {
+            let open = tail.file.metadata()?;
+            if file_id(&current) != file_id(&open) {
+                // the old file was read to the end above, now switch to the new one
+                if let Ok(file) = File::open(&path) {
+                    eprintln!("{}: file replaced, following the new file", path.display());
+                    tail.file = file;
+                    tail.reset();
+                    continue;
+                }
+            } else if current.len() < tail.offset + tail.pending.len() as u64 {
+                eprintln!("{}: file truncated", path.display());
+                tail.file.seek(SeekFrom::Start(0))?;
+                tail.reset();
+                continue;
+            }
+        }
+        thread::sleep(POLL);
+    }
+
+    drop(tx);
+    let _ = printer.join();
+    Ok(())
+}
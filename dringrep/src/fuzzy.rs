@@ -0,0 +1,318 @@
+/*
+Approximate matching (--fuzzy K): finds substrings within Levenshtein distance K of the query.
+
+Ends of approximate matches are found with Myers' bit-parallel algorithm (the pattern's
+DP column is kept in two u64 bit vectors), so a line costs a handful of word operations per
+char no matter how long it is. Only when an end is found is a small DP run backwards from
+it to recover where the hit starts, for highlighting and -o.
+*/
+
+use std::collections::HashMap;
+
+use crate::{Matcher, Span};
+
+// the pattern's DP column has to fit in one machine word
+pub const MAX_FUZZY_LEN: usize = 64;
+
+pub struct FuzzyMatcher {
+    pattern: Vec<char>,
+    max_distance: usize,
+    ignore_case: bool,
+    // bit i set if pattern[i] == c
+    peq_ascii: [u64; 128],
+    peq_other: HashMap<char, u64>,
+}
+
+fn fold(c: char, ignore_case: bool) -> char {
+    if ignore_case {
+        c.to_lowercase().next().unwrap_or(c)
+    } else {
+        c
+    }
+}
+
+impl FuzzyMatcher {
+    pub fn new(query: &str, max_distance: usize, ignore_case: bool) -> Result<Self, String> {
+        let pattern: Vec<char> = query.chars().map(|c| fold(c, ignore_case)).collect();
+        if pattern.is_empty() {
+            return Err("--fuzzy needs a non-empty query".to_string());
+        }
+        if pattern.len() > MAX_FUZZY_LEN {
+            return Err(format!(
+                "--fuzzy queries are limited to {} characters",
+                MAX_FUZZY_LEN
+            ));
+        }
+        if max_distance >= pattern.len() {
+            return Err(format!(
+                "--fuzzy {} would match anything for a {} character query",
+                max_distance,
+                pattern.len()
+            ));
+        }
+
+        let mut peq_ascii = [0u64; 128];
+        let mut peq_other = HashMap::new();
+        for (i, &c) in pattern.iter().enumerate() {
+            if c.is_ascii() {
+                peq_ascii[c as usize] |= 1 << i;
+            } else {
+                *peq_other.entry(c).or_insert(0) |= 1 << i;
+            }
+        }
+
+        Ok(FuzzyMatcher {
+            pattern,
+            max_distance,
+            ignore_case,
+            peq_ascii,
+            peq_other,
+        })
+    }
+
+    fn peq(&self, c: char) -> u64 {
+        if c.is_ascii() {
+            self.peq_ascii[c as usize]
+        } else {
+            self.peq_other.get(&c).copied().unwrap_or(0)
+        }
+    }
+
+    // (char index of the last char, distance) for every end position within max_distance
+    fn match_ends(&self, text: &[char]) -> Vec<(usize, usize)> {
+        let m = self.pattern.len();
+        let mask = if m == 64 { !0 } else { (1u64 << m) - 1 };
+        let high = 1u64 << (m - 1);
+
+        let mut pv = mask;
+        let mut mv = 0u64;
+        let mut score = m;
+        let mut ends = Vec::new();
+
+        for (j, &c) in text.iter().enumerate() {
+            let eq = self.peq(c);
+            let xv = eq | mv;
+            let xh = (((eq & pv).wrapping_add(pv)) ^ pv) | eq;
+            let mut ph = mv | !(xh | pv);
+            let mut mh = pv & xh;
+
+            if ph & high != 0 {
+                score += 1;
+            } else if mh & high != 0 {
+                score -= 1;
+            }
+
+            // searching: the top DP row is all zeros, so nothing is shifted in
+            ph = (ph << 1) & mask;
+            mh = (mh << 1) & mask;
+            pv = (mh | !(xv | ph)) & mask;
+            mv = ph & xv;
+
+            if score <= self.max_distance {
+                ends.push((j, score));
+            }
+        }
+
+        ends
+    }
+
+    // given the last char of a hit, the first one: align the reversed pattern against the
+    // text running backwards from `end` and take the closest alignment
+    fn match_start(&self, text: &[char], end: usize) -> usize {
+        let m = self.pattern.len();
+        let window = (m + self.max_distance).min(end + 1);
+
+        // prev[j]: distance between the last i pattern chars and the j text chars before end
+        let mut prev: Vec<usize> = (0..=window).collect();
+        for i in 1..=m {
+            let mut cur = vec![i; window + 1];
+            let pc = self.pattern[m - i];
+            for j in 1..=window {
+                let tc = text[end + 1 - j];
+                let substitution = prev[j - 1] + usize::from(pc != tc);
+                cur[j] = substitution.min(prev[j] + 1).min(cur[j - 1] + 1);
+            }
+            prev = cur;
+        }
+
+        // fewest edits wins, then the longest hit ("colour" rather than "colo" for color~1)
+        let (len, _) = prev
+            .iter()
+            .enumerate()
+            .min_by_key(|&(j, &d)| (d, std::cmp::Reverse(j)))
+            .unwrap();
+        end + 1 - len
+    }
+}
+
+impl Matcher for FuzzyMatcher {
+    fn matches_query(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().map(|c| fold(c, self.ignore_case)).collect();
+        !self.match_ends(&chars).is_empty()
+    }
+
+    fn find_all(&self, text: &str) -> Vec<Span> {
+        let byte_at: Vec<usize> = text
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(text.len()))
+            .collect();
+        let chars: Vec<char> = text.chars().map(|c| fold(c, self.ignore_case)).collect();
+
+        let mut spans = Vec::new();
+        let mut taken_until = 0;
+        let ends = self.match_ends(&chars);
+
+        // neighbouring end positions belong to the same hit, keep its best one (the last on ties)
+        let mut i = 0;
+        while i < ends.len() {
+            let mut best = ends[i];
+            let mut j = i + 1;
+            while j < ends.len() && ends[j].0 == ends[j - 1].0 + 1 {
+                if ends[j].1 <= best.1 {
+                    best = ends[j];
+                }
+                j += 1;
+            }
+            i = j;
+
+            let start = self.match_start(&chars, best.0);
+            if start < taken_until {
+                continue;
+            }
+            spans.push(Span {
+                start: byte_at[start],
+                end: byte_at[best.0 + 1],
+                pattern: 0,
+            });
+            taken_until = best.0 + 1;
+        }
+
+        spans
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hits(query: &str, k: usize, text: &str) -> Vec<String> {
+        let matcher = FuzzyMatcher::new(query, k, false).unwrap();
+        matcher
+            .find_all(text)
+            .into_iter()
+            .map(|s| text[s.start..s.end].to_string())
+            .collect()
+    }
+
+    // the textbook DP with a free start: distance of the best hit ending at each char
+    fn reference_ends(pattern: &[char], text: &[char], k: usize) -> Vec<(usize, usize)> {
+        let mut col: Vec<usize> = (0..=pattern.len()).collect();
+        let mut ends = Vec::new();
+        for (j, &c) in text.iter().enumerate() {
+            let mut next = vec![0; pattern.len() + 1];
+            for i in 1..=pattern.len() {
+                next[i] = (col[i - 1] + usize::from(pattern[i - 1] != c))
+                    .min(col[i] + 1)
+                    .min(next[i - 1] + 1);
+            }
+            if next[pattern.len()] <= k {
+                ends.push((j, next[pattern.len()]));
+            }
+            col = next;
+        }
+        ends
+    }
+
+    #[test]
+    fn zero_distance_is_exact() {
+        assert_eq!(hits("abc", 0, "xxabcxx abd abc"), ["abc", "abc"]);
+        assert!(hits("abc", 0, "ab c, abd, acb").is_empty());
+    }
+
+    #[test]
+    fn one_edit_of_each_kind() {
+        assert_eq!(hits("color", 1, "the colour"), ["colour"]);
+        assert_eq!(hits("color", 1, "a colr b"), ["colr"]);
+        assert_eq!(hits("color", 1, "a calor b"), ["calor"]);
+        assert!(hits("color", 1, "a cxlxr b").is_empty());
+    }
+
+    #[test]
+    fn ignore_case_and_non_ascii() {
+        let matcher = FuzzyMatcher::new("Straße", 1, true).unwrap();
+        let text = "in der STRASE 5";
+        let spans = matcher.find_all(text);
+        assert_eq!(spans.len(), 1);
+        assert_eq!(&text[spans[0].start..spans[0].end], "STRASE");
+        assert!(matcher.matches_query("strase"));
+        assert!(!matcher.matches_query("strom"));
+    }
+
+    #[test]
+    fn sixty_four_char_pattern_uses_the_whole_word() {
+        let pattern: String = (0..64).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+        let matcher = FuzzyMatcher::new(&pattern, 2, false).unwrap();
+
+        let exact = format!("--{}--", pattern);
+        assert_eq!(matcher.find_all(&exact).len(), 1);
+
+        // the last char is the one the top bit tracks
+        let mut edited = pattern.clone();
+        edited.replace_range(63..64, "#");
+        edited.replace_range(0..1, "#");
+        assert!(matcher.matches_query(&edited));
+        edited.replace_range(30..31, "#");
+        edited.replace_range(31..32, "#");
+        assert!(!matcher.matches_query(&edited));
+
+        let too_long = format!("{}x", pattern);
+        assert!(FuzzyMatcher::new(&too_long, 1, false).is_err());
+    }
+
+    #[test]
+    fn bit_vectors_agree_with_the_dp() {
+        // a small LCG keeps the texts the same on every run
+        let mut seed: u64 = 0x2545f4914f6cdd1d;
+        let mut next = |n: u64| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) % n
+        };
+        for _ in 0..200 {
+            let m = 1 + next(12) as usize;
+            let pattern: Vec<char> = (0..m).map(|_| (b'a' + next(3) as u8) as char).collect();
+            let text: Vec<char> = (0..40).map(|_| (b'a' + next(3) as u8) as char).collect();
+            for k in 0..m.min(4) {
+                let query: String = pattern.iter().collect();
+                let matcher = FuzzyMatcher::new(&query, k, false).unwrap();
+                assert_eq!(
+                    matcher.match_ends(&text),
+                    reference_ends(&pattern, &text, k),
+                    "pattern {} k {} text {}",
+                    query,
+                    k,
+                    text.iter().collect::<String>()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn match_start_prefers_fewest_edits_then_longest() {
+        let matcher = FuzzyMatcher::new("abcd", 1, false).unwrap();
+        let text: Vec<char> = "xxabcdxx".chars().collect();
+        assert_eq!(matcher.match_start(&text, 5), 2);
+        // an end at the very start of the line keeps the window inside the text
+        let text: Vec<char> = "bcd".chars().collect();
+        assert_eq!(matcher.match_start(&text, 2), 0);
+    }
+
+    #[test]
+    fn rejects_queries_it_cant_handle() {
+        assert!(FuzzyMatcher::new("", 0, false).is_err());
+        assert!(FuzzyMatcher::new("abc", 3, false).is_err());
+        assert!(FuzzyMatcher::new("abc", 2, false).is_ok());
+    }
+}
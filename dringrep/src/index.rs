@@ -0,0 +1,683 @@
+/*
+Trigram index for repeated searches of one tree (`dringrep index build DIR`, then `-r --index`).
+
+DIR/.dringrep-index maps every 3-byte sequence (ASCII case folded) to the files containing it,
+and remembers each file's size and mtime. A search turns its literals, or the literals any
+regex match has to start and end with, into trigrams that a matching file must contain. Files
+that lack them and are unchanged since indexing are skipped without being read.
+
+The index only ever rules files out. The walk, filters and search itself are unchanged, and
+anything the index can't vouch for (new, modified or unindexed files, queries without
+usable literals) is searched as usual, so results are the same as without --index.
+*/
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::UNIX_EPOCH;
+
+use regex_syntax::ParserBuilder;
+use regex_syntax::hir::literal::{ExtractKind, Extractor, Seq};
+
+use crate::{Pattern, ThreadPool};
+
+pub const INDEX_FILE: &str = ".dringrep-index";
+// the leading 0xff keeps the file invalid utf-8, so plain searches skip it as binary
+const MAGIC: &[u8] = b"\xffDRIDX1\n";
+
+// three bytes packed into the low 24 bits
+type Trigram = u32;
+
+#[derive(Clone, Copy, PartialEq)]
+struct Fingerprint {
+    size: u64,
+    mtime_secs: i64,
+    mtime_nanos: u32,
+}
+
+// for files the build couldn't read: no real file has 2^32 nanoseconds, so it never matches
+const UNREADABLE: Fingerprint = Fingerprint {
+    size: 0,
+    mtime_secs: 0,
+    mtime_nanos: u32::MAX,
+};
+
+fn fingerprint(metadata: &Metadata) -> Option<Fingerprint> {
+    let mtime = metadata.modified().ok()?;
+    let (mtime_secs, mtime_nanos) = match mtime.duration_since(UNIX_EPOCH) {
+        Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+        Err(e) => (
+            -(e.duration().as_secs() as i64),
+            e.duration().subsec_nanos(),
+        ),
+    };
+    Some(Fingerprint {
+        size: metadata.len(),
+        mtime_secs,
+        mtime_nanos,
+    })
+}
+
+fn trigram(w: &[u8]) -> Trigram {
+    let f = |b: u8| b.to_ascii_lowercase() as u32;
+    f(w[0]) << 16 | f(w[1]) << 8 | f(w[2])
+}
+
+fn trigrams(bytes: &[u8]) -> HashSet<Trigram> {
+    bytes.windows(3).map(trigram).collect()
+}
+
+struct IndexedFile {
+    // relative to the indexed directory
+    path: String,
+    fingerprint: Fingerprint,
+}
+
+struct TrigramIndex {
+    files: Vec<IndexedFile>,
+    // sorted file ids per trigram
+    postings: HashMap<Trigram, Vec<u32>>,
+}
+
+pub struct BuildSummary {
+    pub files: usize,
+    pub read: usize,
+    pub unchanged: usize,
+    pub removed: usize,
+}
+
+// Rebuilds DIR/.dringrep-index. Files whose size and mtime match the previous index keep
+// their postings, so only new and changed files are read again.
+pub fn build_index(dir: &Path, threads: usize) -> io::Result<BuildSummary> {
+    let index_path = dir.join(INDEX_FILE);
+    let old = TrigramIndex::load(&index_path).ok();
+
+    let mut found = Vec::new();
+    collect_files(dir, dir, &mut found)?;
+    found.sort_by(|a, b| a.0.cmp(&b.0));
+
+    // old id -> new id for every file that hasn't changed
+    let mut reused: HashMap<u32, u32> = HashMap::new();
+    let mut to_read = Vec::new();
+    let old_ids: HashMap<&str, (u32, Fingerprint)> = old
+        .iter()
+        .flat_map(|o| o.files.iter().enumerate())
+        .map(|(id, f)| (f.path.as_str(), (id as u32, f.fingerprint)))
+        .collect();
+    for (new_id, (path, fp)) in found.iter().enumerate() {
+        match old_ids.get(path.as_str()) {
+            Some(&(old_id, old_fp)) if old_fp == *fp => {
+                reused.insert(old_id, new_id as u32);
+            }
+            _ => to_read.push(new_id as u32),
+        }
+    }
+    let still_there: HashSet<&str> = found.iter().map(|(path, _)| path.as_str()).collect();
+    let removed = old_ids.keys().filter(|p| !still_there.contains(*p)).count();
+
+    let mut postings: HashMap<Trigram, Vec<u32>> = HashMap::new();
+    if let Some(old) = old {
+        for (t, ids) in old.postings {
+            let ids: Vec<u32> = ids
+                .iter()
+                .filter_map(|id| reused.get(id).copied())
+                .collect();
+            if !ids.is_empty() {
+                postings.insert(t, ids);
+            }
+        }
+    }
+
+    let mut files: Vec<IndexedFile> = found
+        .into_iter()
+        .map(|(path, fingerprint)| IndexedFile { path, fingerprint })
+        .collect();
+
+    // changed files are read on the pool; binary files get no trigrams, so a search (which
+    // would skip them anyway) never has to open them
+    let (tx, rx) = mpsc::channel::<(u32, Option<HashSet<Trigram>>)>();
+    {
+        let pool = ThreadPool::new(threads);
+        for &id in &to_read {
+            let path = dir.join(&files[id as usize].path);
+            let tx = tx.clone();
+            pool.execute(move || {
+                let grams = fs::read(&path)
+                    .ok()
+                    .map(|bytes| match std::str::from_utf8(&bytes) {
+                        Ok(_) => trigrams(&bytes),
+                        Err(_) => HashSet::new(),
+                    });
+                let _ = tx.send((id, grams));
+            });
+        }
+    }
+    drop(tx);
+    for (id, grams) in rx {
+        match grams {
+            Some(grams) => {
+                for t in grams {
+                    postings.entry(t).or_default().push(id);
+                }
+            }
+            None => files[id as usize].fingerprint = UNREADABLE,
+        }
+    }
+    for ids in postings.values_mut() {
+        ids.sort_unstable();
+    }
+
+    let index = TrigramIndex { files, postings };
+    index.save(&index_path)?;
+
+    Ok(BuildSummary {
+        files: index.files.len(),
+        read: to_read.len(),
+        unchanged: reused.len(),
+        removed,
+    })
+}
+
+// regular files below `dir`; symlinks aren't indexed, so --follow searches them normally
+fn collect_files(root: &Path, dir: &Path, out: &mut Vec<(String, Fingerprint)>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let Ok(metadata) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+
+        if metadata.is_dir() {
+            // an unreadable directory is left out, its files just won't be ruled out
+            let _ = collect_files(root, &path, out);
+        } else if metadata.is_file() {
+            let Some(relative) = path.strip_prefix(root).ok().and_then(Path::to_str) else {
+                continue;
+            };
+            if relative == INDEX_FILE || relative.starts_with(&format!("{}.", INDEX_FILE)) {
+                continue;
+            }
+            if let Some(fp) = fingerprint(&metadata) {
+                out.push((relative.to_string(), fp));
+            }
+        }
+    }
+    Ok(())
+}
+
+// little-endian integers, lengths before strings, posting lists as varint deltas
+impl TrigramIndex {
+    fn save(&self, path: &Path) -> io::Result<()> {
+        let mut out = MAGIC.to_vec();
+        out.extend((self.files.len() as u32).to_le_bytes());
+        for f in &self.files {
+            out.extend((f.path.len() as u32).to_le_bytes());
+            out.extend(f.path.as_bytes());
+            out.extend(f.fingerprint.size.to_le_bytes());
+            out.extend(f.fingerprint.mtime_secs.to_le_bytes());
+            out.extend(f.fingerprint.mtime_nanos.to_le_bytes());
+        }
+
+        out.extend((self.postings.len() as u32).to_le_bytes());
+        for (t, ids) in &self.postings {
+            out.extend(t.to_le_bytes());
+            out.extend((ids.len() as u32).to_le_bytes());
+            let mut prev = 0;
+            for &id in ids {
+                write_varint(&mut out, id - prev);
+                prev = id;
+            }
+        }
+
+        // written next to the index and renamed over it, so a search never reads half a file
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, out)?;
+        fs::rename(tmp, path)
+    }
+
+    fn load(path: &Path) -> io::Result<TrigramIndex> {
+        let bytes = fs::read(path)?;
+        let corrupt = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not a valid index, rebuild it", path.display()),
+            )
+        };
+        let mut r = Reader {
+            bytes: bytes.strip_prefix(MAGIC).ok_or_else(corrupt)?,
+        };
+
+        let file_count = r.u32().ok_or_else(corrupt)?;
+        // counts are only trusted as far as the bytes left can hold them
+        let mut files = Vec::with_capacity((file_count as usize).min(r.bytes.len()));
+        for _ in 0..file_count {
+            let len = r.u32().ok_or_else(corrupt)? as usize;
+            let path = r.take(len).ok_or_else(corrupt)?;
+            let path = String::from_utf8(path.to_vec()).map_err(|_| corrupt())?;
+            let fingerprint = Fingerprint {
+                size: r.u64().ok_or_else(corrupt)?,
+                mtime_secs: r.u64().ok_or_else(corrupt)? as i64,
+                mtime_nanos: r.u32().ok_or_else(corrupt)?,
+            };
+            files.push(IndexedFile { path, fingerprint });
+        }
+
+        let gram_count = r.u32().ok_or_else(corrupt)?;
+        let mut postings = HashMap::with_capacity((gram_count as usize).min(r.bytes.len()));
+        for _ in 0..gram_count {
+            let t = r.u32().ok_or_else(corrupt)?;
+            let n = r.u32().ok_or_else(corrupt)?;
+            let mut ids = Vec::with_capacity((n as usize).min(r.bytes.len()));
+            let mut prev: u32 = 0;
+            for _ in 0..n {
+                prev = r
+                    .varint()
+                    .and_then(|delta| prev.checked_add(delta))
+                    .ok_or_else(corrupt)?;
+                if prev >= file_count {
+                    return Err(corrupt());
+                }
+                ids.push(prev);
+            }
+            postings.insert(t, ids);
+        }
+
+        Ok(TrigramIndex { files, postings })
+    }
+
+    // ids of the files that may satisfy `query`, None for every file
+    fn candidates(&self, query: &Query) -> Option<Vec<u32>> {
+        match query {
+            Query::All => None,
+            Query::Trigrams(grams) => {
+                let mut result: Option<Vec<u32>> = None;
+                for t in grams {
+                    let ids = self.postings.get(t).cloned().unwrap_or_default();
+                    result = Some(match result {
+                        None => ids,
+                        Some(acc) => intersect(&acc, &ids),
+                    });
+                }
+                result
+            }
+            Query::And(parts) => parts
+                .iter()
+                .filter_map(|q| self.candidates(q))
+                .reduce(|a, b| intersect(&a, &b)),
+            Query::Or(parts) => {
+                let mut all = Vec::new();
+                for q in parts {
+                    all.extend(self.candidates(q)?);
+                }
+                all.sort_unstable();
+                all.dedup();
+                Some(all)
+            }
+        }
+    }
+}
+
+fn intersect(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (mut i, mut j) = (0, 0);
+    let mut out = Vec::new();
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                out.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    out
+}
+
+fn write_varint(out: &mut Vec<u8>, mut n: u32) {
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < n {
+            return None;
+        }
+        let (head, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Some(head)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take(8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn varint(&mut self) -> Option<u32> {
+        let mut n = 0u32;
+        for shift in (0..35).step_by(7) {
+            let b = self.take(1)?[0];
+            // the fifth byte only has four bits left to give
+            if shift == 28 && b > 0x0f {
+                return None;
+            }
+            n |= ((b & 0x7f) as u32) << shift;
+            if b & 0x80 == 0 {
+                return Some(n);
+            }
+        }
+        None
+    }
+}
+
+// what a file has to contain for the pattern to possibly match in it
+enum Query {
+    All,
+    // every one of these
+    Trigrams(Vec<Trigram>),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+}
+
+fn literal_query(bytes: &[u8]) -> Query {
+    if bytes.len() < 3 {
+        Query::All
+    } else {
+        Query::Trigrams(trigrams(bytes).into_iter().collect())
+    }
+}
+
+// a finite literal sequence means every match starts (or ends) with one of its literals
+fn seq_query(seq: &Seq) -> Query {
+    match seq.literals() {
+        Some(literals) => Query::Or(
+            literals
+                .iter()
+                .map(|l| literal_query(l.as_bytes()))
+                .collect(),
+        ),
+        None => Query::All,
+    }
+}
+
+fn regex_query(pattern: &str, ignore_case: bool) -> Query {
+    let Ok(hir) = ParserBuilder::new()
+        .case_insensitive(ignore_case)
+        .build()
+        .parse(pattern)
+    else {
+        return Query::All;
+    };
+    let prefixes = Extractor::new().kind(ExtractKind::Prefix).extract(&hir);
+    let suffixes = Extractor::new().kind(ExtractKind::Suffix).extract(&hir);
+    Query::And(vec![seq_query(&prefixes), seq_query(&suffixes)])
+}
+
+fn pattern_query(pattern: &Pattern, names: &[String], ignore_case: bool) -> Query {
+    match pattern {
+        Pattern::Literal { .. } | Pattern::MultipleLiteral { .. } => {
+            Query::Or(names.iter().map(|n| literal_query(n.as_bytes())).collect())
+        }
+        Pattern::Regex(re) => regex_query(re.as_str(), ignore_case),
+        Pattern::MultipleRegex { regexes, .. } => Query::Or(
+            regexes
+                .iter()
+                .map(|re| regex_query(re.as_str(), ignore_case))
+                .collect(),
+        ),
+        // no trigrams to require: every indexed file stays a candidate
+        _ => Query::All,
+    }
+}
+
+// what --index consults during a search: per indexed file, its fingerprint and whether it
+// can contain a match
+pub struct IndexFilter {
+    root: PathBuf,
+    files: HashMap<String, (Fingerprint, bool)>,
+}
+
+impl IndexFilter {
+    // uses the nearest .dringrep-index in `start` or one of its parents
+    pub fn open(
+        start: &Path,
+        pattern: &Pattern,
+        names: &[String],
+        ignore_case: bool,
+    ) -> Result<IndexFilter, String> {
+        let root = start
+            .ancestors()
+            .find(|dir| dir.join(INDEX_FILE).is_file())
+            .ok_or_else(|| {
+                format!(
+                    "no {} in {} or its parents, create one with `dringrep index build <dir>`",
+                    INDEX_FILE,
+                    start.display()
+                )
+            })?;
+        let index = TrigramIndex::load(&root.join(INDEX_FILE)).map_err(|e| e.to_string())?;
+
+        let candidates = index.candidates(&pattern_query(pattern, names, ignore_case));
+        let mut is_candidate = vec![candidates.is_none(); index.files.len()];
+        for id in candidates.into_iter().flatten() {
+            is_candidate[id as usize] = true;
+        }
+
+        let files = index
+            .files
+            .into_iter()
+            .zip(is_candidate)
+            .map(|(f, candidate)| (f.path, (f.fingerprint, candidate)))
+            .collect();
+
+        Ok(IndexFilter {
+            root: root.to_path_buf(),
+            files,
+        })
+    }
+
+    // true only for files the index knows cannot match and that are unchanged since then
+    pub fn rules_out(&self, path: &Path) -> bool {
+        let Some(relative) = path.strip_prefix(&self.root).ok().and_then(Path::to_str) else {
+            return false;
+        };
+        let Some(&(indexed, candidate)) = self.files.get(relative) else {
+            return false;
+        };
+        if candidate {
+            return false;
+        }
+        fs::symlink_metadata(path)
+            .ok()
+            .and_then(|m| fingerprint(&m))
+            .is_some_and(|fp| fp == indexed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint_bytes(n: u32) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_varint(&mut out, n);
+        out
+    }
+
+    fn read_varint(bytes: &[u8]) -> Option<u32> {
+        Reader { bytes }.varint()
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        for (n, len) in [
+            (0, 1),
+            (1, 1),
+            (127, 1),
+            (128, 2),
+            (16_383, 2),
+            (16_384, 3),
+            (u32::MAX >> 4, 4),
+            (u32::MAX, 5),
+        ] {
+            let bytes = varint_bytes(n);
+            assert_eq!(bytes.len(), len, "{}", n);
+            assert_eq!(read_varint(&bytes), Some(n));
+        }
+    }
+
+    #[test]
+    fn varint_rejects_truncated_and_oversized() {
+        assert_eq!(read_varint(&[]), None);
+        assert_eq!(read_varint(&[0x80]), None);
+        assert_eq!(read_varint(&[0xff, 0xff, 0xff, 0xff]), None);
+        // more than 32 bits
+        assert_eq!(read_varint(&[0xff, 0xff, 0xff, 0xff, 0x10]), None);
+        assert_eq!(read_varint(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x00]), None);
+    }
+
+    fn file(path: &str, size: u64) -> IndexedFile {
+        IndexedFile {
+            path: path.to_string(),
+            fingerprint: Fingerprint {
+                size,
+                mtime_secs: -5,
+                mtime_nanos: 7,
+            },
+        }
+    }
+
+    fn temp_index(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("dringrep-test-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn postings_round_trip_through_a_file() {
+        let files: Vec<IndexedFile> = (0..300).map(|i| file(&format!("f{}", i), i)).collect();
+        let mut postings = HashMap::new();
+        postings.insert(trigram(b"abc"), vec![0, 1, 2, 299]);
+        postings.insert(trigram(b"xyz"), vec![150]);
+        postings.insert(trigram(b"a\xffb"), (0..300).step_by(7).collect());
+        let index = TrigramIndex { files, postings };
+
+        let path = temp_index("round-trip");
+        index.save(&path).unwrap();
+        let loaded = TrigramIndex::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.files.len(), 300);
+        assert_eq!(loaded.files[299].path, "f299");
+        assert!(loaded.files[42].fingerprint == index.files[42].fingerprint);
+        assert_eq!(loaded.postings, index.postings);
+    }
+
+    #[test]
+    fn load_rejects_corrupt_indexes() {
+        let good = TrigramIndex {
+            files: vec![file("a", 1), file("b", 2)],
+            postings: HashMap::from([(trigram(b"abc"), vec![0, 1])]),
+        };
+        let path = temp_index("corrupt");
+        good.save(&path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        // the posting list is at the very end: [0, 1] as deltas 0 and 1
+        let postings_at = bytes.len() - 2;
+
+        let mut cases = vec![
+            b"not an index".to_vec(),
+            bytes[..bytes.len() - 1].to_vec(),
+            bytes[..MAGIC.len() + 2].to_vec(),
+        ];
+        // an id past the last file
+        let mut past_end = bytes.clone();
+        past_end[postings_at + 1] = 2;
+        cases.push(past_end);
+        // a delta that overflows the id
+        let mut overflow = bytes[..postings_at].to_vec();
+        overflow.push(1);
+        overflow.extend(varint_bytes(u32::MAX));
+        cases.push(overflow);
+
+        // counts far larger than the file
+        let mut huge = bytes[..MAGIC.len()].to_vec();
+        huge.extend(u32::MAX.to_le_bytes());
+        cases.push(huge);
+
+        for case in cases {
+            fs::write(&path, case).unwrap();
+            assert!(TrigramIndex::load(&path).is_err());
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn candidates_follow_the_query() {
+        let index = TrigramIndex {
+            files: (0..4).map(|i| file(&i.to_string(), 0)).collect(),
+            postings: HashMap::from([
+                (trigram(b"abc"), vec![0, 1, 2]),
+                (trigram(b"bcd"), vec![1, 2, 3]),
+                (trigram(b"xyz"), vec![3]),
+            ]),
+        };
+        assert_eq!(index.candidates(&Query::All), None);
+        assert_eq!(index.candidates(&literal_query(b"abcd")), Some(vec![1, 2]));
+        // trigrams fold ASCII case, like the build
+        assert_eq!(index.candidates(&literal_query(b"ABCD")), Some(vec![1, 2]));
+        assert_eq!(index.candidates(&literal_query(b"qqq")), Some(vec![]));
+        assert_eq!(
+            index.candidates(&Query::Or(vec![
+                literal_query(b"xyz"),
+                literal_query(b"abc")
+            ])),
+            Some(vec![0, 1, 2, 3])
+        );
+        // a branch that could be anything makes the whole `or` anything
+        assert_eq!(
+            index.candidates(&Query::Or(vec![literal_query(b"xyz"), Query::All])),
+            None
+        );
+        assert_eq!(
+            index.candidates(&Query::And(vec![Query::All, literal_query(b"xyz")])),
+            Some(vec![3])
+        );
+    }
+
+    #[test]
+    fn regex_queries_need_their_literal_prefixes() {
+        let index = TrigramIndex {
+            files: (0..3).map(|i| file(&i.to_string(), 0)).collect(),
+            postings: HashMap::from([(trigram(b"foo"), vec![0, 2]), (trigram(b"bar"), vec![1, 2])]),
+        };
+        assert_eq!(
+            index.candidates(&regex_query(r"foo\d+bar", false)),
+            Some(vec![2])
+        );
+        assert_eq!(
+            index.candidates(&regex_query(r"(foo|bar)x?", false)),
+            Some(vec![0, 1, 2])
+        );
+        assert_eq!(index.candidates(&regex_query(r"\w+", false)), None);
+    }
+
+    #[test]
+    fn intersect_sorted_lists() {
+        assert_eq!(intersect(&[1, 3, 5, 7], &[2, 3, 4, 7, 9]), vec![3, 7]);
+        assert_eq!(intersect(&[], &[1]), Vec::<u32>::new());
+    }
+}
@@ -0,0 +1,382 @@
+/*
+--json-path: search structured logs by field instead of by line.
+
+Each line of an NDJSON file is parsed on its own; a file that is one JSON document (pretty
+printed, say) is parsed whole. A top-level array, on one line or over many, is split so
+every element is a record. Paths start at the record, so `$.name` rather than `$[*].name`.
+The pattern is only tried on the values the paths select, so
+`--json-path '$.request.user' --query bob` no longer hits a key called "bob" or bob in some
+other field.
+
+Paths: `$.a.b`, or just `a.b`; `[0]` indexes arrays, `*` / `[*]` takes every key or
+element, and `['odd.key']` quotes a key. Strings are matched without their quotes, other
+values as JSON text.
+
+Matching records are printed as they are; --json-values prints the matched values instead.
+Lines that aren't valid JSON are reported on stderr and skipped.
+*/
+
+use std::borrow::Cow;
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::{Config, LineMatch, Matcher, Span, Stats, paint_spans, redact_spans};
+
+#[derive(Clone, PartialEq)]
+enum Step {
+    Key(String),
+    Index(usize),
+    Any,
+}
+
+pub struct JsonPath {
+    steps: Vec<Step>,
+}
+
+impl JsonPath {
+    pub fn parse(path: &str) -> Result<Self, String> {
+        let invalid = |why: &str| format!("Invalid --json-path `{}`: {}", path, why);
+        let rest = path.strip_prefix('$').unwrap_or(path);
+        let mut chars = rest.chars().peekable();
+        let mut steps = Vec::new();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '.' => {}
+                '[' => {
+                    let mut inner = String::new();
+                    loop {
+                        match chars.next() {
+                            Some(']') => break,
+                            Some(c) => inner.push(c),
+                            None => return Err(invalid("unclosed `[`")),
+                        }
+                    }
+                    let quoted = inner
+                        .strip_prefix('\'')
+                        .and_then(|s| s.strip_suffix('\''))
+                        .or_else(|| inner.strip_prefix('"').and_then(|s| s.strip_suffix('"')));
+                    steps.push(match (quoted, inner.as_str()) {
+                        (Some(key), _) => Step::Key(key.to_string()),
+                        (None, "*") => Step::Any,
+                        (None, n) => Step::Index(
+                            n.parse()
+                                .map_err(|_| invalid("expected an index, `*` or a quoted key"))?,
+                        ),
+                    });
+                }
+                _ => {
+                    // a bare key runs up to the next `.` or `[`
+                    let mut key = c.to_string();
+                    while let Some(&c) = chars.peek() {
+                        if c == '.' || c == '[' {
+                            break;
+                        }
+                        key.push(c);
+                        chars.next();
+                    }
+                    steps.push(if key == "*" {
+                        Step::Any
+                    } else {
+                        Step::Key(key)
+                    });
+                }
+            }
+        }
+        Ok(JsonPath { steps })
+    }
+
+    // every value the path reaches in `value`
+    fn select<'v>(&self, value: &'v Value) -> Vec<&'v Value> {
+        let mut current = vec![value];
+        for step in &self.steps {
+            current = current
+                .into_iter()
+                .flat_map(|v| -> Vec<&Value> {
+                    match (step, v) {
+                        (Step::Key(k), Value::Object(map)) => map.get(k).into_iter().collect(),
+                        (Step::Index(i), Value::Array(items)) => {
+                            items.get(*i).into_iter().collect()
+                        }
+                        (Step::Any, Value::Object(map)) => map.values().collect(),
+                        (Step::Any, Value::Array(items)) => items.iter().collect(),
+                        _ => Vec::new(),
+                    }
+                })
+                .collect();
+        }
+        current
+    }
+}
+
+pub struct JsonQuery {
+    pub paths: Vec<JsonPath>,
+    // print the matched values rather than the records holding them
+    pub values: bool,
+}
+
+// what the pattern sees of a value
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+// `contents` starts at line `first_line + 1` and byte `first_byte`, like search_chunk;
+// `path` only names the file in warnings about malformed lines
+pub fn search_json<'a>(
+    config: &Config,
+    query: &JsonQuery,
+    path: &Path,
+    contents: &'a str,
+    first_line: usize,
+    first_byte: usize,
+) -> Vec<LineMatch<'a>> {
+    // one document over several lines, or a top-level array however it is laid out
+    let trimmed = contents.trim();
+    if let Ok(document) = serde_json::from_str::<Value>(trimmed)
+        && (trimmed.contains('\n') || document.is_array())
+    {
+        return search_elements(config, query, path, document, first_line + 1, first_byte);
+    }
+
+    let mut results = Vec::new();
+    let mut line_start = first_byte;
+    for (i, raw) in contents.split_inclusive('\n').enumerate() {
+        let offset = line_start;
+        line_start += raw.len();
+        let line = raw.trim_end_matches(['\n', '\r']);
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line_number = first_line + i + 1;
+        match serde_json::from_str::<Value>(line) {
+            Ok(array @ Value::Array(_)) => {
+                results.extend(search_elements(
+                    config,
+                    query,
+                    path,
+                    array,
+                    line_number,
+                    offset,
+                ));
+            }
+            Ok(record) => {
+                if let Some(found) =
+                    search_record(config, query, path, &record, line, line_number, offset)
+                {
+                    results.extend(found);
+                }
+            }
+            Err(e) => eprintln!(
+                "{}:{}: not valid JSON, skipped ({})",
+                config.display_path(path),
+                line_number,
+                e
+            ),
+        }
+    }
+    results
+}
+
+// every element of an array is a record, anything else is one; they are all reported at
+// the value's first line, there is no telling where a record is
+fn search_elements(
+    config: &Config,
+    query: &JsonQuery,
+    path: &Path,
+    value: Value,
+    line_number: usize,
+    offset: usize,
+) -> Vec<LineMatch<'static>> {
+    let records = match value {
+        Value::Array(items) => items,
+        record => vec![record],
+    };
+    let mut results = Vec::new();
+    for record in &records {
+        let text = record.to_string();
+        if let Some(found) = search_record(config, query, path, record, &text, line_number, offset)
+        {
+            results.extend(found.into_iter().map(LineMatch::into_owned));
+        }
+    }
+    results
+}
+
+// the results for one record, None if it isn't selected
+fn search_record<'a>(
+    config: &Config,
+    query: &JsonQuery,
+    path: &Path,
+    record: &Value,
+    text: &'a str,
+    line_number: usize,
+    offset: usize,
+) -> Option<Vec<LineMatch<'a>>> {
+    let pattern = &config.pattern;
+    let hits: Vec<&Value> = query
+        .paths
+        .iter()
+        .flat_map(|p| p.select(record))
+        .filter(|v| pattern.selects_line(&value_text(v), config.file_scope))
+        .collect();
+    if hits.is_empty() ^ config.invert {
+        return None;
+    }
+
+    let paint = |text: &str, spans: &[Span]| {
+        if config.highlight {
+            paint_spans(text, spans, &config.colors)
+        } else {
+            text.to_string()
+        }
+    };
+
+    // -o and --json-values: one result per hit or per matched value
+    if config.only_matching || (query.values && !config.invert) {
+        if let Some(stats) = &config.stats {
+            Stats::add(&stats.matched_lines, 1);
+        }
+        let mut results = Vec::new();
+        for value in hits {
+            let value = value_text(value);
+            let spans = pattern.find_all(&value);
+            if let Some(stats) = &config.stats {
+                Stats::add(&stats.total_matches, spans.len().max(1) as u64);
+            }
+            let parts: Vec<(String, Vec<Span>)> = if config.only_matching {
+                spans
+                    .into_iter()
+                    .filter(|s| s.start < s.end)
+                    .map(|s| {
+                        let hit = value[s.start..s.end].to_string();
+                        let span = Span {
+                            start: 0,
+                            end: hit.len(),
+                            pattern: s.pattern,
+                        };
+                        (hit, vec![span])
+                    })
+                    .collect()
+            } else if config.redact {
+                vec![redact_spans(&value, &spans)]
+            } else {
+                vec![(value, spans)]
+            };
+            for (text, spans) in parts {
+                results.push(LineMatch {
+                    line_number,
+                    column: 1,
+                    byte_offset: offset,
+                    text: Cow::Owned(paint(&text, &spans)),
+                    spans,
+                });
+            }
+        }
+        return Some(results);
+    }
+
+    // the record as it is, with every place the matched text appears in it marked: a value's
+    // position in the line is unknown, and its text there may be escaped differently
+    let mut spans = Vec::new();
+    let mut unlocated = false;
+    for value in &hits {
+        let value = value_text(value);
+        for s in pattern
+            .find_all(&value)
+            .into_iter()
+            .filter(|s| s.start < s.end)
+        {
+            let found = occurrences(text, &value[s.start..s.end]);
+            unlocated |= found.is_empty();
+            spans.extend(found.into_iter().map(|(start, end)| Span {
+                start,
+                end,
+                pattern: s.pattern,
+            }));
+        }
+    }
+    // rather than print a hit that can't be masked
+    if config.redact && unlocated {
+        eprintln!(
+            "{}:{}: a match could not be found in the record to redact it, skipped",
+            config.display_path(path),
+            line_number
+        );
+        return None;
+    }
+    spans.sort_by_key(|s| (s.start, s.pattern));
+    spans.dedup();
+    if let Some(stats) = &config.stats {
+        Stats::add(&stats.matched_lines, 1);
+        Stats::add(&stats.total_matches, spans.len().max(1) as u64);
+    }
+
+    let first = spans.first().map_or(0, |s| s.start);
+    let column = text[..first].chars().count() + 1;
+    let (text, spans) = if config.redact {
+        let (masked, spans) = redact_spans(text, &spans);
+        (Cow::Owned(masked), spans)
+    } else {
+        (Cow::Borrowed(text), spans)
+    };
+    let text = if config.highlight {
+        Cow::Owned(paint(&text, &spans))
+    } else {
+        text
+    };
+    Some(vec![LineMatch {
+        line_number,
+        column,
+        byte_offset: offset + first,
+        spans,
+        text,
+    }])
+}
+
+// byte ranges of `hit` in the raw record, as it is and as JSON escapes it
+fn occurrences(text: &str, hit: &str) -> Vec<(usize, usize)> {
+    let escaped = serde_json::to_string(hit).unwrap_or_default();
+    let escaped = &escaped[1..escaped.len() - 1];
+    let mut found: Vec<(usize, usize)> = text
+        .match_indices(hit)
+        .map(|(at, _)| (at, at + hit.len()))
+        .collect();
+    if escaped != hit {
+        found.extend(
+            text.match_indices(escaped)
+                .map(|(at, _)| (at, at + escaped.len())),
+        );
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Args;
+    use clap::Parser;
+
+    fn texts(contents: &str) -> Vec<String> {
+        let args = Args::parse_from(["dringrep", "--query", "2", "--json-path", "$.a"]);
+        let config = Config::try_from(args).unwrap();
+        let query = config.json_path.as_ref().unwrap();
+        search_json(&config, query, Path::new("t.json"), contents, 0, 0)
+            .iter()
+            .map(|m| m.text.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn arrays_are_split_however_they_are_laid_out() {
+        let expected = [r#"{"a":2}"#];
+        assert_eq!(texts(r#"[{"a":1},{"a":2}]"#), expected);
+        assert_eq!(texts("[\n  {\"a\": 1},\n  {\"a\": 2}\n]\n"), expected);
+        assert_eq!(texts("{\"a\":1}\n[{\"a\":1},{\"a\":2}]\n"), expected);
+        // a record is printed as it is written
+        assert_eq!(texts("{\"a\": 2}\n"), [r#"{"a": 2}"#]);
+    }
+}
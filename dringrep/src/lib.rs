@@ -18,113 +18,372 @@ optional: add support for numbers (for example flags that expect numeric values
 
 */
 
+mod archive;
+mod color;
+mod expr;
+mod follow;
+mod fuzzy;
+mod index;
+mod jsonpath;
+#[cfg(feature = "pcre")]
+mod pcre;
+mod pre;
+mod preset;
+mod secrets;
+mod stats;
+mod table;
+mod timewindow;
+mod tui;
 mod types;
 mod utils;
+mod walk;
+mod watch;
 
 use std::borrow::Cow;
+use std::io::BufRead;
 
-use aho_corasick::{AhoCorasick, AhoCorasickBuilder};
-use colored::Colorize;
-pub use types::{Args, Config, FileResult, Pattern, ThreadPool};
+pub use color::{ColorChoice, ColorSpecs, Style};
+pub use expr::QueryExpr;
+pub use follow::follow_file;
+pub use fuzzy::FuzzyMatcher;
+pub use index::{BuildSummary, IndexFilter, build_index};
+pub use jsonpath::{JsonPath, JsonQuery, search_json};
+#[cfg(feature = "pcre")]
+pub use pcre::PcreMatcher;
+pub use pre::Preprocessor;
+pub use preset::PresetMatcher;
+pub use secrets::{SecretsSummary, scan_secrets};
+pub use stats::{SkipReason, Stats};
+pub use table::{FieldRef, Table, search_table};
+pub use timewindow::{TimeWindow, parse_when};
+pub use tui::run_tui;
+pub use types::{
+    Args, CancelToken, Command, Config, FileResult, IndexCommand, LineMatch, OutputFormat,
+    PathStyle, Pattern, PoolHandle, Span, ThreadPool, WorkerStats,
+};
 pub use utils::{print_each_result, print_results, process_batch};
+pub use walk::{Entry, is_hidden, walk_parallel};
+pub use watch::{Snapshot, tee_results, watch};
 
-pub fn count_matches(matches: &Vec<(usize, String)>) -> usize {
+pub fn count_matches(matches: &[LineMatch]) -> usize {
     // wrong for recursive, fix
 
-    return matches.len();
+    matches.len()
 }
 
 pub trait Matcher {
     fn matches_query(&self, text: &str) -> bool;
+
+    // every hit on the line, ordered by start; matchers without spans report none
+    fn find_all(&self, _text: &str) -> Vec<Span> {
+        Vec::new()
+    }
 }
 
 impl Matcher for Pattern {
     fn matches_query(&self, text: &str) -> bool {
         match self {
             Pattern::Regex(re) => re.is_match(text),
+            Pattern::MultipleRegex { set, .. } => set.is_match(text),
+            Pattern::Expr(expr) => expr.matches_query(text),
+            Pattern::Fuzzy(fuzzy) => fuzzy.matches_query(text),
+            Pattern::Preset(preset) => preset.matches_query(text),
+            #[cfg(feature = "pcre")]
+            Pattern::Pcre(pcre) => pcre.matches_query(text),
             Pattern::Literal { pattern, .. } => pattern.is_match(text),
             // check if correct later
             Pattern::MultipleLiteral { pattern, .. } => pattern.is_match(text),
         }
     }
+
+    fn find_all(&self, text: &str) -> Vec<Span> {
+        match self {
+            Pattern::Regex(re) => re
+                .find_iter(text)
+                .map(|m| Span {
+                    start: m.start(),
+                    end: m.end(),
+                    pattern: 0,
+                })
+                .collect(),
+            Pattern::MultipleRegex { set, regexes } => {
+                let mut spans: Vec<Span> = set
+                    .matches(text)
+                    .into_iter()
+                    .flat_map(|i| {
+                        regexes[i].find_iter(text).map(move |m| Span {
+                            start: m.start(),
+                            end: m.end(),
+                            pattern: i,
+                        })
+                    })
+                    .collect();
+                spans.sort_by_key(|s| (s.start, s.pattern));
+                spans
+            }
+            Pattern::Expr(expr) => expr.find_all(text),
+            Pattern::Fuzzy(fuzzy) => fuzzy.find_all(text),
+            Pattern::Preset(preset) => preset.find_all(text),
+            #[cfg(feature = "pcre")]
+            Pattern::Pcre(pcre) => pcre.find_all(text),
+            Pattern::Literal { pattern, .. } | Pattern::MultipleLiteral { pattern, .. } => pattern
+                .find_iter(text)
+                .map(|m| Span {
+                    start: m.start(),
+                    end: m.end(),
+                    pattern: m.pattern().as_usize(),
+                })
+                .collect(),
+        }
+    }
 }
 
-pub fn highlight_match(line: &str, pat: &Pattern) -> String {
-    let mut highlighted_string = String::from("");
+impl Pattern {
+    // --file-scope: does the file as a whole satisfy the query?
+    pub fn matches_file(&self, contents: &str) -> bool {
+        match self {
+            Pattern::Expr(expr) => expr.matches_file(contents),
+            _ => contents.lines().any(|line| self.matches_query(line)),
+        }
+    }
 
-    match pat {
-        Pattern::Literal {
-            pattern,
-            case_insensitive,
+    // in a file that passed --file-scope, the lines worth showing are the ones where a
+    // (non-negated) part of the expression hits
+    pub(crate) fn selects_line(&self, line: &str, file_scope: bool) -> bool {
+        match self {
+            Pattern::Expr(expr) if file_scope => expr.any_positive_leaf(line),
+            _ => self.matches_query(line),
         }
-        | Pattern::MultipleLiteral {
-            pattern,
-            case_insensitive,
-        } => {
-            let matches: Vec<(usize, usize)> = pattern
-                .find_iter(line)
-                .map(|m| (m.start(), m.end()))
-                .collect();
+    }
+}
 
-            // matches right now is [(0,3), (5,8), (24,27)]
+// paints every span in the colour of the pattern that produced it; where spans from
+// different patterns overlap, the one starting first wins
+pub fn highlight_match<M: Matcher + ?Sized>(
+    line: &str,
+    matcher: &M,
+    colors: &ColorSpecs,
+) -> String {
+    paint_spans(line, &matcher.find_all(line), colors)
+}
 
-            let mut last = 0;
-            for (start, end) in matches {
-                highlighted_string.push_str(&line[last..start]);
+// the painting half of highlight_match, for callers that already have the spans
+pub fn paint_spans(line: &str, spans: &[Span], colors: &ColorSpecs) -> String {
+    let mut highlighted_string = String::from("");
 
-                highlighted_string.push_str(&line[start..end].red().underline().bold().to_string());
+    let mut last = 0;
+    for span in spans {
+        if span.start < last || span.start == span.end {
+            continue;
+        }
+        highlighted_string.push_str(&line[last..span.start]);
 
-                last = end;
-            }
-            highlighted_string.push_str(&line[last..]);
+        let style = colors.for_pattern(span.pattern);
+        highlighted_string.push_str(&style.paint(&line[span.start..span.end]).to_string());
 
-            highlighted_string
-        }
-        Pattern::Regex(re) => {
-            let matches: Vec<(usize, usize)> =
-                re.find_iter(line).map(|x| (x.start(), x.end())).collect();
+        last = span.end;
+    }
+    highlighted_string.push_str(&line[last..]);
 
-            for (index, char) in line.char_indices() {
-                let inside_match = matches.iter().any(|(s, e)| index >= *s && index < *e);
+    highlighted_string
+}
 
-                if inside_match {
-                    highlighted_string
-                        .push_str(&char.to_string().red().underline().bold().to_string());
-                } else {
-                    highlighted_string.push(char);
-                }
-            }
+// --redact: the text of every span becomes as many `*` as it has characters, and the spans
+// move to where the masks are. Overlapping spans mask their union, so no tail is left in clear.
+pub fn redact_spans(line: &str, spans: &[Span]) -> (String, Vec<Span>) {
+    let mut masked = String::with_capacity(line.len());
+    let mut moved = Vec::with_capacity(spans.len());
 
-            highlighted_string
+    let mut last = 0;
+    // where the masked run that ends at `last` starts, in `line` and in `masked`
+    let mut run = (0, 0);
+    for span in spans {
+        if span.start == span.end {
+            continue;
+        }
+        if span.start >= last {
+            masked.push_str(&line[last..span.start]);
+            run = (span.start, masked.len());
+        }
+        if span.end > last {
+            let from = last.max(span.start);
+            masked.extend(line[from..span.end].chars().map(|_| '*'));
+            last = span.end;
         }
+        let at = |i: usize| run.1 + line[run.0..i].chars().count();
+        moved.push(Span {
+            start: at(span.start),
+            end: at(span.end),
+            pattern: span.pattern,
+        });
     }
+    masked.push_str(&line[last..]);
+
+    (masked, moved)
 }
 
-fn process_lines<'a>(
-    query: &Pattern,
+pub(crate) fn process_lines<'a>(
+    config: &Config,
     contents: &'a str,
-    invert: bool,
-    highlight: bool,
-) -> Vec<(usize, Cow<'a, str>)> {
+    first_line: usize,
+    first_byte: usize,
+) -> Vec<LineMatch<'a>> {
+    let query = &config.pattern;
+    let invert = config.invert;
+    let highlight = config.highlight.then_some(&config.colors);
+    let stats = config.stats.as_ref();
+
+    let mut line_start = first_byte;
     contents
-        .lines()
+        .split_inclusive('\n')
         .enumerate()
-        .filter_map(|(i, line)| {
-            let matched = query.matches_query(line);
+        .flat_map(|(i, raw)| {
+            let offset = line_start;
+            line_start += raw.len();
+            let line = raw.strip_suffix('\n').unwrap_or(raw);
+            let line = line.strip_suffix('\r').unwrap_or(line);
+
+            let matched = query.selects_line(line, config.file_scope);
             if matched ^ invert {
-                if highlight {
-                    return Some((i + 1, Cow::Owned(highlight_match(line, query))));
+                let spans = if invert {
+                    Vec::new()
                 } else {
-                    return Some((i + 1, Cow::Borrowed(line)));
+                    query.find_all(line)
+                };
+                if let Some(stats) = stats {
+                    Stats::add(&stats.matched_lines, 1);
+                    // a line without spans (e.g. inverted) still counts as one hit
+                    Stats::add(&stats.total_matches, spans.len().max(1) as u64);
+                }
+                let line_number = first_line + i + 1;
+
+                // -o: one result per match holding just the matched text
+                if config.only_matching {
+                    return spans
+                        .into_iter()
+                        .filter(|s| s.start < s.end)
+                        .map(|s| {
+                            let hit = &line[s.start..s.end];
+                            LineMatch {
+                                line_number,
+                                column: line[..s.start].chars().count() + 1,
+                                byte_offset: offset + s.start,
+                                spans: vec![Span {
+                                    start: 0,
+                                    end: hit.len(),
+                                    pattern: s.pattern,
+                                }],
+                                text: match highlight {
+                                    Some(colors) => Cow::Owned(
+                                        colors.for_pattern(s.pattern).paint(hit).to_string(),
+                                    ),
+                                    None => Cow::Borrowed(hit),
+                                },
+                            }
+                        })
+                        .collect();
                 }
+
+                let first = spans.first().map_or(0, |s| s.start);
+                let column = line[..first].chars().count() + 1;
+                let (text, spans) = if config.redact {
+                    let (masked, spans) = redact_spans(line, &spans);
+                    let text = match highlight {
+                        Some(colors) => paint_spans(&masked, &spans, colors),
+                        None => masked,
+                    };
+                    (Cow::Owned(text), spans)
+                } else {
+                    let text = match highlight {
+                        Some(colors) => Cow::Owned(highlight_match(line, query, colors)),
+                        None => Cow::Borrowed(line),
+                    };
+                    (text, spans)
+                };
+                vec![LineMatch {
+                    line_number,
+                    column,
+                    byte_offset: offset + first,
+                    spans,
+                    text,
+                }]
             } else {
-                None
+                Vec::new()
             }
         })
         .collect()
 }
-pub fn search<'a>(config: &Config, contents: &'a str) -> Vec<(usize, Cow<'a, str>)> {
-    process_lines(&config.pattern, contents, config.invert, config.highlight)
+pub fn search<'a>(config: &Config, contents: &'a str) -> Vec<LineMatch<'a>> {
+    search_chunk(config, contents, 0, 0)
+}
+
+// `contents` starts at line `first_line + 1` and byte `first_byte` of the file,
+// so chunks of one big file still report global positions
+pub fn search_chunk<'a>(
+    config: &Config,
+    contents: &'a str,
+    first_line: usize,
+    first_byte: usize,
+) -> Vec<LineMatch<'a>> {
+    if config.file_scope && !config.pattern.matches_file(contents) {
+        return Vec::new();
+    }
+    if let Some(table) = &config.table {
+        return search_table(config, table, contents, first_line, first_byte);
+    }
+    if let Some(window) = &config.time_window {
+        return window.search(config, contents, first_line, first_byte);
+    }
+    process_lines(config, contents, first_line, first_byte)
+}
+
+// reads line by line and returns on the first selected line instead of loading the whole file
+// Ok(None) means the file is not valid utf-8 and is treated as binary
+pub fn has_match<R: BufRead>(config: &Config, mut reader: R) -> std::io::Result<Option<bool>> {
+    let mut buf = Vec::new();
+
+    // a file-scope expression can only be decided once the whole file is seen, a record
+    // of --csv can span lines and --since needs the times of the lines before
+    if config.table.is_some() || config.time_window.is_some() {
+        let read = reader.read_to_end(&mut buf)?;
+        if let Some(stats) = &config.stats {
+            Stats::add(&stats.bytes_read, read as u64);
+        }
+        let Ok(contents) = std::str::from_utf8(&buf) else {
+            return Ok(None);
+        };
+        return Ok(Some(!search_chunk(config, contents, 0, 0).is_empty()));
+    }
+    if config.file_scope {
+        let read = reader.read_to_end(&mut buf)?;
+        if let Some(stats) = &config.stats {
+            Stats::add(&stats.bytes_read, read as u64);
+        }
+        let Ok(contents) = std::str::from_utf8(&buf) else {
+            return Ok(None);
+        };
+        return Ok(Some(config.pattern.matches_file(contents) ^ config.invert));
+    }
+
+    loop {
+        buf.clear();
+        let read = reader.read_until(b'\n', &mut buf)?;
+        if read == 0 {
+            return Ok(Some(false));
+        }
+        if let Some(stats) = &config.stats {
+            Stats::add(&stats.bytes_read, read as u64);
+        }
+        let Ok(line) = std::str::from_utf8(&buf) else {
+            return Ok(None);
+        };
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        if config.pattern.matches_query(line) ^ config.invert {
+            return Ok(Some(true));
+        }
+    }
 }
 
 #[cfg(test)]
@@ -132,4 +391,85 @@ mod tests {
     // test all flags work correctly
     // text various regex patterns
     // text error handling
+    use super::*;
+    use crate::preset::PresetMatcher;
+    use clap::Parser;
+
+    fn config(args: &[&str]) -> Result<Config, String> {
+        let args = Args::parse_from(std::iter::once("dringrep").chain(args.iter().copied()));
+        Config::try_from(args)
+    }
+
+    #[test]
+    fn file_scope_lists_and_prints_the_same_files() {
+        let config = config(&["--expr", "alpha & beta", "--file-scope"]).unwrap();
+        for (contents, selected) in [
+            ("alpha\nother\nbeta\n", true),
+            ("alpha beta\n", true),
+            ("alpha\nother\n", false),
+            ("", false),
+        ] {
+            let listed = has_match(&config, contents.as_bytes()).unwrap();
+            let printed = !search_chunk(&config, contents, 0, 0).is_empty();
+            assert_eq!(listed, Some(selected), "{:?}", contents);
+            assert_eq!(printed, selected, "{:?}", contents);
+        }
+    }
+
+    #[test]
+    fn file_scope_rejects_invert() {
+        assert!(config(&["--expr", "alpha & beta", "--file-scope", "-i"]).is_err());
+        assert!(config(&["--expr", "alpha & beta", "-i"]).is_ok());
+    }
+
+    fn span(start: usize, end: usize, pattern: usize) -> Span {
+        Span {
+            start,
+            end,
+            pattern,
+        }
+    }
+
+    #[test]
+    fn redact_masks_separate_spans() {
+        let (masked, spans) = redact_spans("a bob b al c", &[span(2, 5, 0), span(8, 10, 1)]);
+        assert_eq!(masked, "a *** b ** c");
+        assert_eq!(spans, vec![span(2, 5, 0), span(8, 10, 1)]);
+    }
+
+    #[test]
+    fn redact_masks_union_of_overlapping_spans() {
+        // a phone number that is the start of a longer card number
+        let (masked, spans) = redact_spans("0123456789abc", &[span(2, 6, 0), span(2, 10, 1)]);
+        assert_eq!(masked, "01********abc");
+        assert_eq!(spans, vec![span(2, 6, 0), span(2, 10, 1)]);
+
+        // a span inside an earlier one, and one running past its end
+        let (masked, spans) = redact_spans(
+            "0123456789abc",
+            &[span(1, 5, 0), span(2, 3, 1), span(4, 8, 2)],
+        );
+        assert_eq!(masked, "0*******89abc");
+        assert_eq!(spans, vec![span(1, 5, 0), span(2, 3, 1), span(4, 8, 2)]);
+    }
+
+    #[test]
+    fn redact_counts_characters_not_bytes() {
+        let (masked, spans) = redact_spans("x café y", &[span(2, 7, 0), span(5, 8, 1)]);
+        assert_eq!(masked, "x *****y");
+        assert_eq!(spans, vec![span(2, 6, 0), span(5, 7, 1)]);
+    }
+
+    #[test]
+    fn redact_leaves_no_digit_of_overlapping_presets() {
+        let presets =
+            PresetMatcher::new(&["phone".to_string(), "credit-card".to_string()]).unwrap();
+        let line = "card 555 234 5678 895 end";
+        let spans = presets.find_all(line);
+        assert_eq!(spans.len(), 2);
+        let (masked, spans) = redact_spans(line, &spans);
+        assert_eq!(masked, "card **************** end");
+        // both presets keep their span, so --json still names the card
+        assert_eq!(spans, vec![span(5, 17, 0), span(5, 21, 1)]);
+    }
 }
@@ -1,125 +1,191 @@
 use clap::Parser;
 
-extern crate num_cpus;
-
-mod types;
-mod utils;
-
 use dringrep::{
-    Args, Config, FileResult, ThreadPool, count_matches, print_results, process_batch, search,
+    Args, Command, Config, Entry, FileResult, IndexCommand, ThreadPool, build_index, follow_file,
+    print_results, process_batch, run_tui, scan_secrets, tee_results, walk_parallel, watch,
 };
 
 use std::env;
 use std::error::Error;
-
 use std::fs;
-use std::fs::File;
-use std::io;
-use std::io::Write;
+use std::path::PathBuf;
+
 use std::process;
 use std::sync::Arc;
-use std::sync::Mutex;
 use std::sync::mpsc;
 use std::time::Instant;
 
-use walkdir::DirEntry;
-use walkdir::WalkDir;
-
 fn main() -> std::io::Result<()> {
     let args = Args::parse();
 
-    let config: Config = args.into();
+    let threads = args
+        .threads
+        .map(usize::from)
+        .unwrap_or_else(|| num_cpus::get().saturating_sub(1).max(1));
+    match &args.command {
+        Some(Command::Index {
+            action: IndexCommand::Build { dir },
+        }) => {
+            match build_index(dir, threads) {
+                Ok(summary) => println!(
+                    "indexed {} files in {} ({} read, {} unchanged, {} removed)",
+                    summary.files,
+                    dir.display(),
+                    summary.read,
+                    summary.unchanged,
+                    summary.removed
+                ),
+                Err(e) => {
+                    eprintln!("Application error: {e}");
+                    process::exit(1);
+                }
+            }
+            return Ok(());
+        }
+        Some(Command::Secrets {
+            dir,
+            allowlist,
+            baseline,
+            update_baseline,
+        }) => {
+            let summary = match scan_secrets(
+                dir,
+                allowlist.as_deref(),
+                baseline.as_deref(),
+                *update_baseline,
+                threads,
+                args.json,
+            ) {
+                Ok(summary) => summary,
+                // 1 means secrets were found, so errors get their own code
+                Err(e) => {
+                    eprintln!("Application error: {e}");
+                    process::exit(2);
+                }
+            };
+            if *update_baseline {
+                eprintln!(
+                    "recorded {} findings in the baseline ({} files scanned)",
+                    summary.baselined, summary.files
+                );
+                return Ok(());
+            }
+            eprintln!(
+                "{} new secrets in {} files ({} known from the baseline)",
+                summary.new, summary.files, summary.baselined
+            );
+            if summary.new > 0 {
+                process::exit(1);
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+
+    // the query is typed in the TUI, so it starts before a Config can exist
+    if args.tui {
+        match run_tui(args) {
+            Ok(Some(selected)) => println!("{}", selected),
+            Ok(None) => process::exit(1),
+            Err(e) => {
+                eprintln!("Application error: {e}");
+                process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    let config = match Config::try_from(args) {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    // one global switch so every coloured string respects --color / NO_COLOR
+    colored::control::set_override(config.color);
     let start = Instant::now();
 
     // dont need return value so we use if let
-    if let Err(e) = run(config) {
+    if let Err(e) = run(Arc::clone(&config)) {
         eprintln!("Application error: {e}");
         process::exit(1);
     }
-    let duration = start.elapsed();
-    println!("Finished in {:?}", duration);
+
+    if let Some(stats) = &config.stats {
+        stats.print(start.elapsed(), config.json);
+    }
 
     Ok(())
 }
 
-fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let file_counter = Arc::new(Mutex::new(0));
+fn run(config: Arc<Config>) -> Result<(), Box<dyn Error>> {
     let current = env::current_dir().unwrap();
-    const BATCH_SIZE: usize = 128;
-    let num_of_cpus = num_cpus::get();
-    let pool_size = if num_of_cpus > 1 { num_of_cpus - 1 } else { 1 };
-    let mut batch = Vec::with_capacity(BATCH_SIZE);
     let (tx, rx) = mpsc::channel::<FileResult>();
+    // --watch compares later changes against every file's first matches
+    let (rx, snapshot) = if config.watch {
+        let (rx, snapshot) = tee_results(rx);
+        (rx, Some(snapshot))
+    } else {
+        (rx, None)
+    };
 
-    let config = Arc::new(config);
-    let file_counter_clone = Arc::clone(&file_counter);
-    let thread_pool = ThreadPool::new(pool_size, file_counter_clone);
+    let root = if config.recursive {
+        current
+    } else {
+        PathBuf::from(&config.file_path)
+    };
 
     if config.recursive {
-        let mut entry: DirEntry;
-
-        for entry_walkdir in WalkDir::new(current) {
-            entry = entry_walkdir?;
-
-            batch.push(entry);
-
-            if batch.len() == BATCH_SIZE {
-                let config = Arc::clone(&config);
-                let tx = tx.clone();
-                thread_pool.execute(move || {
-                    process_batch(batch, tx, config, false);
-                });
-                batch = Vec::with_capacity(BATCH_SIZE); // reset batch
-            }
-        }
-        // if less than 25 files, send the remaining
-        if !batch.is_empty() {
-            let tx = tx.clone();
-            let config = Arc::clone(&config);
-            thread_pool.execute(move || {
-                process_batch(batch, tx, config, false);
-            });
+        let thread_pool = ThreadPool::new(config.threads);
+        walk_parallel(root.clone(), Arc::clone(&config), thread_pool.handle(), tx);
+
+        // results stream in while the walk is still running; the channel closes once
+        // the last job (and with it the last sender) is gone
+        print_results(rx, Arc::clone(&config));
+        if let Some(stats) = &config.stats {
+            stats.set_workers(thread_pool.worker_stats());
         }
-        drop(tx);
         drop(thread_pool);
-
-        print_results(rx, config);
     } else {
         // currently dont use threads for a single file , maybe add ?
 
-        let entry = match WalkDir::new(&config.file_path)
-            .max_depth(1)
-            .into_iter()
-            .next()
-        {
-            Some(Ok(e)) => e,
-            Some(Err(e)) => {
-                eprintln!("Error reading directory: {}", e);
+        let path = root.clone();
+        match fs::metadata(&path) {
+            Ok(m) if m.is_file() => {}
+            Ok(_) => {
+                eprintln!(
+                    "{} is not a file, use -r to search directories",
+                    path.display()
+                );
                 return Ok(());
             }
-            None => {
-                eprintln!("Entry was not found in current directory");
+            Err(e) => {
+                eprintln!("Error reading {}: {}", path.display(), e);
                 return Ok(());
             }
-        };
+        }
 
-        // figure out a way to break it into chunks and feed it to the batch
-        // issue is that batch only takes directories right now
-        // how can I give batch only a part of the files contents?
-        // let contents = Arc::new()
+        if config.follow {
+            drop(tx);
+            drop(rx);
+            follow_file(config, path)?;
+            return Ok(());
+        }
 
-        batch.push(entry);
+        let batch = vec![Entry::new(path, 0)];
 
         {
             let tx = tx.clone();
             let config = Arc::clone(&config);
-            process_batch(batch, tx, config, true);
+            process_batch(batch, tx, config, true)?;
         } // dropping config to use later
         drop(tx);
-        drop(thread_pool);
-        print_results(rx, config);
+        print_results(rx, Arc::clone(&config));
+    }
 
-        println!("The number of processed files was: 1");
+    if let Some(snapshot) = snapshot {
+        watch(config, root, snapshot.join().unwrap_or_default())?;
     }
 
     Ok(())
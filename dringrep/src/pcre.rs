@@ -0,0 +1,87 @@
+/*
+-P/--pcre: patterns the `regex` crate rejects, i.e. lookaround ("foo(?!bar)") and
+backreferences ("(\w+)\s+\1"), run on fancy-regex's backtracking engine.
+
+Backtracking can blow up exponentially on patterns like "(a+)+$", so every match attempt
+gets a budget of backtracking steps. A line that runs out of budget is treated as not
+matching, and the first time it happens a warning says so.
+*/
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use fancy_regex::{Error, Regex, RegexBuilder, RuntimeError};
+
+use crate::{Matcher, Span};
+
+// fancy-regex's own default
+pub const DEFAULT_BACKTRACK_LIMIT: usize = 1_000_000;
+
+pub struct PcreMatcher {
+    regex: Regex,
+    budget: usize,
+    warned: AtomicBool,
+}
+
+impl PcreMatcher {
+    pub fn new(pattern: &str, ignore_case: bool, budget: usize) -> Result<Self, String> {
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(ignore_case)
+            .backtrack_limit(budget)
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(PcreMatcher {
+            regex,
+            budget,
+            warned: AtomicBool::new(false),
+        })
+    }
+
+    // a line that fails to match, for whatever reason, is reported once and then skipped
+    fn give_up(&self, error: Error, text: &str) {
+        if self.warned.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let reason = match error {
+            Error::RuntimeError(RuntimeError::BacktrackLimitExceeded) => format!(
+                "ran out of its budget of {} backtracking steps (raise it with --pcre-budget)",
+                self.budget
+            ),
+            e => e.to_string(),
+        };
+        let preview: String = text.chars().take(60).collect();
+        eprintln!(
+            "warning: --pcre pattern {} on a line starting `{}`; lines like it are treated as not matching",
+            reason, preview
+        );
+    }
+}
+
+impl Matcher for PcreMatcher {
+    fn matches_query(&self, text: &str) -> bool {
+        match self.regex.is_match(text) {
+            Ok(matched) => matched,
+            Err(e) => {
+                self.give_up(e, text);
+                false
+            }
+        }
+    }
+
+    fn find_all(&self, text: &str) -> Vec<Span> {
+        let mut spans = Vec::new();
+        for m in self.regex.find_iter(text) {
+            match m {
+                Ok(m) => spans.push(Span {
+                    start: m.start(),
+                    end: m.end(),
+                    pattern: 0,
+                }),
+                Err(e) => {
+                    self.give_up(e, text);
+                    break;
+                }
+            }
+        }
+        spans
+    }
+}
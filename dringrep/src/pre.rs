@@ -0,0 +1,74 @@
+/*
+--pre: search what a converter prints instead of a file's raw bytes.
+
+The command is run once per file with the path as its only argument (no shell), and its
+stdout is searched in place of the file. Results keep the original path. With --pre-glob
+only files matching one of the globs are converted, the rest are read as usual:
+
+    dringrep --query invoice -r --pre ./pdf2txt.sh --pre-glob '*.pdf'
+*/
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+pub struct Preprocessor {
+    command: String,
+    // None: every file goes through the command
+    globs: Option<GlobSet>,
+}
+
+impl Preprocessor {
+    pub fn new(command: String, globs: &[String]) -> Result<Self, String> {
+        let globs = if globs.is_empty() {
+            None
+        } else {
+            let mut builder = GlobSetBuilder::new();
+            for glob in globs {
+                let glob = Glob::new(glob).map_err(|e| format!("Invalid --pre-glob: {}", e))?;
+                builder.add(glob);
+            }
+            let set = builder
+                .build()
+                .map_err(|e| format!("Invalid --pre-glob: {}", e))?;
+            Some(set)
+        };
+        Ok(Preprocessor { command, globs })
+    }
+
+    // `*.pdf` matches in any directory, as `*` also crosses `/`
+    pub fn applies_to(&self, path: &Path) -> bool {
+        self.globs.as_ref().is_none_or(|set| set.is_match(path))
+    }
+
+    // the command's stdout, or why there is none
+    pub fn run(&self, path: &Path) -> Result<Vec<u8>, String> {
+        let output = Command::new(&self.command)
+            .arg(path)
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| {
+                format!(
+                    "couldn't run preprocessor `{}` on {}: {}",
+                    self.command,
+                    path.display(),
+                    e
+                )
+            })?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let reason = stderr.lines().next().unwrap_or("").trim();
+            return Err(format!(
+                "preprocessor `{}` failed on {} ({}){}{}",
+                self.command,
+                path.display(),
+                output.status,
+                if reason.is_empty() { "" } else { ": " },
+                reason
+            ));
+        }
+        Ok(output.stdout)
+    }
+}
@@ -0,0 +1,373 @@
+/*
+--preset: built-in patterns for personal data, e.g. `--preset email,credit-card`.
+
+A regex alone finds far too much (every 16-digit order number looks like a card, every
+version string like an IP), so each hit is checked before it counts:
+
+    email        local part and domain labels well formed, lengths within RFC 5321
+    ipv4         four octets of 0-255 without leading zeros, not part of a longer dotted run
+    ipv6         parses as an IPv6 address and stands on its own (not `Foo::Bar`)
+    credit-card  13-19 digits passing the Luhn check
+    iban         country code, length 15-34 and the mod-97 checksum
+    phone        +country numbers of 8-15 digits, or North American (NXX) NXX-XXXX
+
+Spans carry the preset's position in the list, so every preset gets its own colour and
+name in --json output.
+*/
+
+use std::net::Ipv6Addr;
+
+use regex::Regex;
+
+use crate::{Matcher, Span};
+
+// the whole line and the hit's byte range, for checks that look at the neighbours
+type Validator = fn(&str, usize, usize) -> bool;
+
+struct Preset {
+    name: &'static str,
+    regex: &'static str,
+    valid: Validator,
+}
+
+const PRESETS: &[Preset] = &[
+    Preset {
+        name: "email",
+        regex: r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}",
+        valid: valid_email,
+    },
+    Preset {
+        name: "ipv4",
+        regex: r"\b\d{1,3}(?:\.\d{1,3}){3}\b",
+        valid: valid_ipv4,
+    },
+    Preset {
+        name: "ipv6",
+        regex: r"[0-9A-Fa-f:.]*:[0-9A-Fa-f:.]*",
+        valid: valid_ipv6,
+    },
+    Preset {
+        name: "credit-card",
+        regex: r"\b\d(?:[ -]?\d){12,18}\b",
+        valid: valid_card,
+    },
+    Preset {
+        name: "iban",
+        regex: r"\b[A-Z]{2}\d{2}(?: ?[A-Z0-9]{4}){2,7}(?: ?[A-Z0-9]{1,4})?\b",
+        valid: valid_iban,
+    },
+    Preset {
+        name: "phone",
+        regex: r"\+\d{1,3}(?:[ .-]?\(?\d{1,4}\)?){2,5}|\(?\b\d{3}\)?[ .-]?\d{3}[ .-]\d{4}\b",
+        valid: valid_phone,
+    },
+];
+
+pub struct PresetMatcher {
+    // (regex, validator) per selected preset, in --preset order
+    presets: Vec<(Regex, Validator)>,
+}
+
+impl PresetMatcher {
+    pub fn new(names: &[String]) -> Result<Self, String> {
+        let presets = names
+            .iter()
+            .map(|name| {
+                let preset = PRESETS.iter().find(|p| p.name == name).ok_or_else(|| {
+                    let known: Vec<&str> = PRESETS.iter().map(|p| p.name).collect();
+                    format!("Unknown preset `{}` (known: {})", name, known.join(", "))
+                })?;
+                let regex = Regex::new(preset.regex).map_err(|e| e.to_string())?;
+                Ok((regex, preset.valid))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(PresetMatcher { presets })
+    }
+}
+
+impl Matcher for PresetMatcher {
+    fn matches_query(&self, text: &str) -> bool {
+        self.presets
+            .iter()
+            .any(|(re, valid)| re.find_iter(text).any(|m| valid(text, m.start(), m.end())))
+    }
+
+    fn find_all(&self, text: &str) -> Vec<Span> {
+        let mut spans: Vec<Span> = self
+            .presets
+            .iter()
+            .enumerate()
+            .flat_map(|(i, (re, valid))| {
+                re.find_iter(text)
+                    .filter(|m| valid(text, m.start(), m.end()))
+                    .map(move |m| Span {
+                        start: m.start(),
+                        end: m.end(),
+                        pattern: i,
+                    })
+            })
+            .collect();
+        spans.sort_by_key(|s| (s.start, s.pattern));
+        spans
+    }
+}
+
+fn before(line: &str, start: usize) -> Option<char> {
+    line[..start].chars().next_back()
+}
+
+fn after(line: &str, end: usize) -> Option<char> {
+    line[end..].chars().next()
+}
+
+fn valid_email(line: &str, start: usize, end: usize) -> bool {
+    let Some((local, domain)) = line[start..end].split_once('@') else {
+        return false;
+    };
+    local.len() <= 64
+        && end - start <= 254
+        && !local.starts_with('.')
+        && !local.ends_with('.')
+        && !local.contains("..")
+        && domain
+            .split('.')
+            .all(|label| !label.is_empty() && !label.starts_with('-') && !label.ends_with('-'))
+}
+
+fn valid_ipv4(line: &str, start: usize, end: usize) -> bool {
+    // 1.2.3.4.5 is a version or an OID, not an address
+    let dotted = |c: Option<char>| c == Some('.');
+    let rest = &line[end..];
+    if dotted(before(line, start))
+        || (rest.starts_with('.') && rest[1..].starts_with(|c: char| c.is_ascii_digit()))
+    {
+        return false;
+    }
+    line[start..end]
+        .split('.')
+        .all(|octet| octet.parse::<u8>().is_ok() && (octet == "0" || !octet.starts_with('0')))
+}
+
+fn valid_ipv6(line: &str, start: usize, end: usize) -> bool {
+    let alnum = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    let candidate = &line[start..end];
+    // `::` alone is valid but is almost always a path separator in code
+    candidate.chars().filter(|c| c.is_ascii_hexdigit()).count() >= 2
+        && !alnum(before(line, start))
+        && !alnum(after(line, end))
+        && candidate.parse::<Ipv6Addr>().is_ok()
+}
+
+fn digits(s: &str) -> Vec<u32> {
+    s.chars().filter_map(|c| c.to_digit(10)).collect()
+}
+
+fn luhn(digits: &[u32]) -> bool {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| match i % 2 {
+            0 => d,
+            _ if d * 2 > 9 => d * 2 - 9,
+            _ => d * 2,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+fn valid_card(line: &str, start: usize, end: usize) -> bool {
+    let d = digits(&line[start..end]);
+    (13..=19).contains(&d.len()) && d.iter().any(|&x| x != d[0]) && luhn(&d)
+}
+
+fn valid_iban(line: &str, start: usize, end: usize) -> bool {
+    let iban: String = line[start..end].chars().filter(|c| *c != ' ').collect();
+    if !(15..=34).contains(&iban.len()) {
+        return false;
+    }
+    // the first four characters move to the end and letters count as 10-35; the whole
+    // number mod 97 has to be 1, computed piecewise so it fits in a u64
+    let (head, tail) = iban.split_at(4);
+    let mut rem: u64 = 0;
+    for c in tail.chars().chain(head.chars()) {
+        let Some(value) = c.to_digit(36) else {
+            return false;
+        };
+        let width = if value < 10 { 10 } else { 100 };
+        rem = (rem * width + value as u64) % 97;
+    }
+    rem == 1
+}
+
+fn valid_phone(line: &str, start: usize, end: usize) -> bool {
+    // part of a longer number, or of a dotted one like an IP (a full stop after it is fine)
+    let rest = &line[end..];
+    let continues = rest.starts_with(|c: char| c.is_ascii_digit())
+        || (rest.starts_with('.') && rest[1..].starts_with(|c: char| c.is_ascii_digit()));
+    if continues || before(line, start).is_some_and(|c| c.is_ascii_digit() || c == '.') {
+        return false;
+    }
+    let hit = &line[start..end];
+    let d = digits(hit);
+    if hit.starts_with('+') {
+        return (8..=15).contains(&d.len());
+    }
+    // North American: area code and exchange don't start with 0 or 1
+    d.len() == 10 && d[0] >= 2 && d[3] >= 2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn found(preset: &str, text: &str) -> Vec<String> {
+        PresetMatcher::new(&[preset.to_string()])
+            .unwrap()
+            .find_all(text)
+            .into_iter()
+            .map(|s| text[s.start..s.end].to_string())
+            .collect()
+    }
+
+    fn whole(valid: Validator, s: &str) -> bool {
+        valid(s, 0, s.len())
+    }
+
+    #[test]
+    fn luhn_known_numbers() {
+        for good in [
+            "4111111111111111",
+            "5555555555554444",
+            "378282246310005",
+            "4012888888881881",
+            "6011 1111 1111 1117",
+            "4111-1111-1111-1111",
+        ] {
+            assert!(whole(valid_card, good), "{}", good);
+        }
+        for bad in [
+            "4111111111111112",
+            "1234567812345678",
+            // Luhn-valid, but nobody's card
+            "0000000000000000",
+            // too short and too long
+            "4111111111",
+            "41111111111111111111",
+        ] {
+            assert!(!whole(valid_card, bad), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn cards_in_text() {
+        assert_eq!(
+            found(
+                "credit-card",
+                "paid with 4111 1111 1111 1111, order 4111111111111112"
+            ),
+            ["4111 1111 1111 1111"]
+        );
+    }
+
+    #[test]
+    fn iban_known_numbers() {
+        for good in [
+            "GB82WEST12345698765432",
+            "GB82 WEST 1234 5698 7654 32",
+            "DE89370400440532013000",
+            "FR1420041010050500013M02606",
+            "NL91ABNA0417164300",
+            "NO9386011117947",
+        ] {
+            assert!(whole(valid_iban, good), "{}", good);
+        }
+        for bad in [
+            // one digit off, and two swapped
+            "GB82WEST12345698765433",
+            "GB82WEST12345698765423",
+            "DE89370400440532013001",
+            // too short to be any country's
+            "NO93860111179",
+            "GB82WEST1234569876543!",
+        ] {
+            assert!(!whole(valid_iban, bad), "{}", bad);
+        }
+        assert_eq!(
+            found(
+                "iban",
+                "to DE89 3704 0044 0532 0130 00 or DE89 3704 0044 0532 0130 01"
+            ),
+            ["DE89 3704 0044 0532 0130 00"]
+        );
+    }
+
+    #[test]
+    fn ipv4_octets_and_neighbours() {
+        assert_eq!(
+            found("ipv4", "from 192.168.1.10 and 0.0.0.0 to 255.255.255.255"),
+            ["192.168.1.10", "0.0.0.0", "255.255.255.255"]
+        );
+        for bad in [
+            "256.1.1.1",
+            "1.2.3.999",
+            "01.2.3.4",
+            "version 1.2.3.4.5",
+            "oid 2.1.2.3.4",
+        ] {
+            assert!(found("ipv4", bad).is_empty(), "{}", bad);
+        }
+        // a full stop ending the sentence is not part of the address
+        assert_eq!(found("ipv4", "ping 10.0.0.1."), ["10.0.0.1"]);
+    }
+
+    #[test]
+    fn ipv6_stands_on_its_own() {
+        assert_eq!(
+            found("ipv6", "via 2001:db8::8a2e:370:7334 and fe80::1"),
+            ["2001:db8::8a2e:370:7334", "fe80::1"]
+        );
+        for bad in [
+            "std::io::Result",
+            "Foo::Bar",
+            "12:30:45",
+            "HashMap::new",
+            "::",
+        ] {
+            assert!(found("ipv6", bad).is_empty(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn emails() {
+        assert_eq!(
+            found("email", "mail bob.smith+tag@mail.example.co.uk, not a@b"),
+            ["bob.smith+tag@mail.example.co.uk"]
+        );
+        for bad in [".bob@example.com", "bob.@example.com", "b..ob@example.com"] {
+            assert!(!whole(valid_email, bad), "{}", bad);
+        }
+        assert!(!whole(
+            valid_email,
+            &format!("{}@example.com", "a".repeat(65))
+        ));
+        assert!(!whole(valid_email, "bob@-example.com"));
+    }
+
+    #[test]
+    fn phones() {
+        assert_eq!(
+            found("phone", "call +44 20 7946 0958 or (415) 555-2671."),
+            ["+44 20 7946 0958", "(415) 555-2671"]
+        );
+        for bad in ["015-555-2671", "415-155-2671", "ref 4155552671999", "+1 23"] {
+            assert!(found("phone", bad).is_empty(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn unknown_presets_are_named() {
+        let err = PresetMatcher::new(&["ssn".to_string()]).err().unwrap();
+        assert!(err.contains("`ssn`") && err.contains("credit-card"));
+    }
+}
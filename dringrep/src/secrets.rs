@@ -0,0 +1,554 @@
+/*
+`dringrep secrets [DIR]`: scan a tree for committed credentials with a bundled rule set.
+
+Every rule has keywords and a regex. One Aho-Corasick automaton over all keywords picks the
+lines (and rules) worth a closer look, so most lines never reach a regex. A regex hit is
+only reported when the secret part is random enough (Shannon entropy per character), which
+keeps `password = "changeme"` style placeholders out.
+
+Findings can be suppressed three ways:
+  - `dringrep:allow` anywhere on the line
+  - an allowlist file (--allowlist, or DIR/.dringrep-allowlist when it exists), one entry
+    per line: `path:GLOB` skips files, `rule:ID` turns a rule off, anything else is a
+    regex and lines it matches are ignored; `#` starts a comment
+  - a baseline (--baseline FILE): findings recorded in it by --update-baseline are known
+    and not reported again. They are keyed by rule, path and secret, so moving a line
+    doesn't make it new, and the baseline holds a hash rather than the secret.
+
+The exit code is 1 when new secrets were found.
+*/
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, mpsc};
+
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use regex::Regex;
+use serde_json::{Value, json};
+
+use crate::ThreadPool;
+
+const ALLOWLIST_FILE: &str = ".dringrep-allowlist";
+// suppresses every finding on its line
+const INLINE_ALLOW: &str = "dringrep:allow";
+// longer files are generated or data, not config
+const MAX_FILE_SIZE: u64 = 2 * 1024 * 1024;
+
+struct Rule {
+    id: &'static str,
+    // lowercase; any of them on a line makes the rule a candidate
+    keywords: &'static [&'static str],
+    // group 1 is the secret if there is one, otherwise the whole match
+    regex: &'static str,
+    // minimum bits per character of the secret, None for rules that are certain already
+    min_entropy: Option<f64>,
+}
+
+const RULES: &[Rule] = &[
+    Rule {
+        id: "aws-access-key-id",
+        keywords: &[
+            "akia", "asia", "agpa", "aida", "aroa", "anpa", "anva", "aipa",
+        ],
+        regex: r"\b((?:AKIA|ASIA|AGPA|AIDA|AROA|ANPA|ANVA|AIPA)[A-Z0-9]{16})\b",
+        min_entropy: Some(3.0),
+    },
+    Rule {
+        id: "aws-secret-access-key",
+        keywords: &["aws"],
+        regex: r#"(?i)aws.{0,20}secret.{0,20}[=:]\s*["']?([A-Za-z0-9/+=]{40})\b"#,
+        min_entropy: Some(4.0),
+    },
+    Rule {
+        id: "github-token",
+        keywords: &["ghp_", "gho_", "ghu_", "ghs_", "ghr_", "github_pat_"],
+        regex: r"\b((?:ghp|gho|ghu|ghs|ghr)_[A-Za-z0-9]{36}|github_pat_[A-Za-z0-9_]{82})\b",
+        min_entropy: Some(3.0),
+    },
+    Rule {
+        id: "private-key",
+        keywords: &["-----begin"],
+        regex: r"-----BEGIN (?:[A-Z]+ )*PRIVATE KEY(?: BLOCK)?-----",
+        min_entropy: None,
+    },
+    Rule {
+        id: "jwt",
+        keywords: &["eyj"],
+        regex: r"\b(eyJ[A-Za-z0-9_-]{10,}\.eyJ[A-Za-z0-9_-]{10,}\.[A-Za-z0-9_-]{10,})",
+        min_entropy: Some(3.0),
+    },
+    Rule {
+        id: "generic-password",
+        keywords: &[
+            "password", "passwd", "pwd", "secret", "token", "api_key", "apikey",
+        ],
+        regex: r#"(?i)(?:password|passwd|pwd|secret|token|api_?key)[\w.-]*["']?\s*[:=]\s*["']?([^\s"',;]{8,})"#,
+        min_entropy: Some(3.0),
+    },
+];
+
+// bits of information per character
+fn entropy(s: &str) -> f64 {
+    let mut counts = [0usize; 256];
+    for b in s.bytes() {
+        counts[b as usize] += 1;
+    }
+    let len = s.len() as f64;
+    counts
+        .iter()
+        .filter(|&&n| n > 0)
+        .map(|&n| {
+            let p = n as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+// FNV-1a: stable across builds and platforms, unlike the std hasher
+fn fingerprint(rule: &str, path: &str, secret: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for part in [rule, path, secret] {
+        for b in part.bytes().chain([0]) {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{:016x}", hash)
+}
+
+// enough of the secret to recognise it, not enough to use it
+fn redact(secret: &str) -> String {
+    let shown: String = secret.chars().take(4).collect();
+    let hidden = secret.chars().count().saturating_sub(4).min(16);
+    format!("{}{}", shown, "*".repeat(hidden))
+}
+
+struct Scanner {
+    prefilter: AhoCorasick,
+    // keyword index -> rule index
+    keyword_rule: Vec<usize>,
+    regexes: Vec<Regex>,
+    disabled: HashSet<&'static str>,
+    skip_paths: GlobSet,
+    ignore_lines: Vec<Regex>,
+}
+
+struct Finding {
+    rule: &'static str,
+    // relative to the scanned directory
+    path: String,
+    line_number: usize,
+    secret: String,
+    fingerprint: String,
+}
+
+#[derive(Default)]
+pub struct SecretsSummary {
+    pub files: usize,
+    pub new: usize,
+    pub baselined: usize,
+}
+
+impl Scanner {
+    fn new(allowlist: Option<&str>) -> Result<Self, String> {
+        let mut keywords = Vec::new();
+        let mut keyword_rule = Vec::new();
+        for (i, rule) in RULES.iter().enumerate() {
+            for k in rule.keywords {
+                keywords.push(*k);
+                keyword_rule.push(i);
+            }
+        }
+        let prefilter = AhoCorasickBuilder::new()
+            .ascii_case_insensitive(true)
+            .build(&keywords)
+            .map_err(|e| e.to_string())?;
+        let regexes = RULES
+            .iter()
+            .map(|r| Regex::new(r.regex).map_err(|e| e.to_string()))
+            .collect::<Result<_, _>>()?;
+
+        let mut disabled = HashSet::new();
+        let mut skip_paths = GlobSetBuilder::new();
+        let mut ignore_lines = Vec::new();
+        for (n, entry) in allowlist.unwrap_or("").lines().enumerate() {
+            let entry = entry.trim();
+            let invalid = |e: &dyn std::fmt::Display| format!("allowlist line {}: {}", n + 1, e);
+            if entry.is_empty() || entry.starts_with('#') {
+                continue;
+            }
+            if let Some(glob) = entry.strip_prefix("path:") {
+                skip_paths.add(Glob::new(glob.trim()).map_err(|e| invalid(&e))?);
+            } else if let Some(id) = entry.strip_prefix("rule:") {
+                let id = id.trim();
+                let rule = RULES
+                    .iter()
+                    .find(|r| r.id == id)
+                    .ok_or_else(|| invalid(&format!("no rule called `{}`", id)))?;
+                disabled.insert(rule.id);
+            } else {
+                ignore_lines.push(Regex::new(entry).map_err(|e| invalid(&e))?);
+            }
+        }
+
+        Ok(Scanner {
+            prefilter,
+            keyword_rule,
+            regexes,
+            disabled,
+            skip_paths: skip_paths.build().map_err(|e| e.to_string())?,
+            ignore_lines,
+        })
+    }
+
+    fn scan(&self, path: &str, contents: &str) -> Vec<Finding> {
+        let mut findings: Vec<Finding> = Vec::new();
+        for (i, line) in contents.lines().enumerate() {
+            let mut candidates: Vec<usize> = self
+                .prefilter
+                .find_overlapping_iter(line)
+                .map(|m| self.keyword_rule[m.pattern().as_usize()])
+                .filter(|&r| !self.disabled.contains(RULES[r].id))
+                .collect();
+            if candidates.is_empty()
+                || line.contains(INLINE_ALLOW)
+                || self.ignore_lines.iter().any(|re| re.is_match(line))
+            {
+                continue;
+            }
+            candidates.sort_unstable();
+            candidates.dedup();
+            let mut line_findings = 0;
+
+            for r in candidates {
+                let rule = &RULES[r];
+                for caps in self.regexes[r].captures_iter(line) {
+                    let secret = caps.get(1).unwrap_or_else(|| caps.get(0).unwrap()).as_str();
+                    if rule.min_entropy.is_some_and(|min| entropy(secret) < min) {
+                        continue;
+                    }
+                    // `token = "ghp_..."` is one GitHub token, not also a generic one; the
+                    // specific rules come first
+                    let line_start = findings.len() - line_findings;
+                    if findings[line_start..]
+                        .iter()
+                        .any(|f| f.secret.contains(secret))
+                    {
+                        continue;
+                    }
+                    line_findings += 1;
+                    findings.push(Finding {
+                        rule: rule.id,
+                        path: path.to_string(),
+                        line_number: i + 1,
+                        secret: secret.to_string(),
+                        fingerprint: fingerprint(rule.id, path, secret),
+                    });
+                }
+            }
+        }
+        findings
+    }
+}
+
+// every regular file below `dir` except .git; dotfiles are kept, .env is where secrets live
+fn collect_files(dir: &Path, out: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(metadata) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+        if metadata.is_dir() {
+            if entry.file_name() != ".git" {
+                collect_files(&path, out);
+            }
+        } else if metadata.is_file() && metadata.len() <= MAX_FILE_SIZE {
+            out.push(path);
+        }
+    }
+}
+
+fn load_baseline(path: &Path) -> io::Result<HashSet<String>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        // no baseline yet: nothing is known
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(e) => return Err(e),
+    };
+    let baseline: Value = serde_json::from_str(&text).map_err(io::Error::other)?;
+    Ok(baseline["findings"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|f| f["fingerprint"].as_str().map(str::to_string))
+        .collect())
+}
+
+fn save_baseline(path: &Path, findings: &[Finding]) -> io::Result<()> {
+    let findings: Vec<Value> = findings
+        .iter()
+        .map(|f| {
+            json!({
+                "fingerprint": f.fingerprint,
+                "rule": f.rule,
+                "path": f.path,
+                "line_number": f.line_number,
+            })
+        })
+        .collect();
+    let text = serde_json::to_string_pretty(&json!({ "findings": findings }))?;
+    fs::write(path, text + "\n")
+}
+
+fn print_finding(finding: &Finding, json: bool) {
+    if json {
+        let line = json!({
+            "type": "secret",
+            "rule": finding.rule,
+            "path": finding.path,
+            "line_number": finding.line_number,
+            "secret": redact(&finding.secret),
+            "fingerprint": finding.fingerprint,
+        });
+        println!("{}", line);
+    } else {
+        println!(
+            "{}:{}: [{}] {}",
+            finding.path,
+            finding.line_number,
+            finding.rule,
+            redact(&finding.secret)
+        );
+    }
+}
+
+// Scans `dir` and prints the findings that aren't in the baseline. With `update_baseline`
+// every finding is written to the baseline instead and nothing counts as new.
+pub fn scan_secrets(
+    dir: &Path,
+    allowlist: Option<&Path>,
+    baseline: Option<&Path>,
+    update_baseline: bool,
+    threads: usize,
+    json: bool,
+) -> Result<SecretsSummary, String> {
+    let default_allowlist = dir.join(ALLOWLIST_FILE);
+    let allowlist = match allowlist {
+        Some(path) => {
+            Some(fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?)
+        }
+        None => fs::read_to_string(&default_allowlist).ok(),
+    };
+    let scanner = Arc::new(Scanner::new(allowlist.as_deref())?);
+    let known = match baseline {
+        Some(path) if !update_baseline => {
+            load_baseline(path).map_err(|e| format!("{}: {}", path.display(), e))?
+        }
+        _ => HashSet::new(),
+    };
+
+    let mut files = Vec::new();
+    collect_files(dir, &mut files);
+    // the baseline and allowlist mention secrets' places, they aren't secrets themselves
+    let own_files: Vec<PathBuf> = baseline
+        .iter()
+        .map(|p| p.to_path_buf())
+        .chain([default_allowlist])
+        .filter_map(|p| fs::canonicalize(p).ok())
+        .collect();
+
+    let (tx, rx) = mpsc::channel::<Vec<Finding>>();
+    let mut summary = SecretsSummary::default();
+    {
+        let pool = ThreadPool::new(threads);
+        for path in files {
+            let relative = path.strip_prefix(dir).unwrap_or(&path).to_path_buf();
+            if scanner.skip_paths.is_match(&relative)
+                || fs::canonicalize(&path).is_ok_and(|p| own_files.contains(&p))
+            {
+                continue;
+            }
+            summary.files += 1;
+            let scanner = Arc::clone(&scanner);
+            let tx = tx.clone();
+            pool.execute(move || {
+                // binary files can't hold a text secret worth reporting
+                let Ok(bytes) = fs::read(&path) else {
+                    return;
+                };
+                let Ok(contents) = std::str::from_utf8(&bytes) else {
+                    return;
+                };
+                let _ = tx.send(scanner.scan(&relative.to_string_lossy(), contents));
+            });
+        }
+    }
+    drop(tx);
+
+    let mut findings: Vec<Finding> = rx.into_iter().flatten().collect();
+    findings.sort_by(|a, b| (&a.path, a.line_number).cmp(&(&b.path, b.line_number)));
+
+    if update_baseline && let Some(path) = baseline {
+        save_baseline(path, &findings).map_err(|e| format!("{}: {}", path.display(), e))?;
+        summary.baselined = findings.len();
+        return Ok(summary);
+    }
+
+    for finding in &findings {
+        if known.contains(&finding.fingerprint) {
+            summary.baselined += 1;
+        } else {
+            summary.new += 1;
+            print_finding(finding, json);
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the samples are split so this file doesn't trip the scanner (or anyone else's)
+    const AWS_KEY_ID: &str = concat!("AKIA", "IOSFODNN7EXAMPLE");
+    const AWS_SECRET: &str = concat!("wJalrXUtnFEMI/K7MDENG/", "bPxRfiCYEXAMPLEKEY");
+    const GITHUB_TOKEN: &str = concat!("ghp_", "aB3dE5fG7hJ9kL1mN3pQ5rS7tU9vW1xY3z05");
+    const JWT: &str = concat!(
+        "eyJhbGciOiJIUzI1NiJ9.",
+        "eyJzdWIiOiIxMjM0NTY3ODkwIn0.",
+        "dozjgNryP4J3jVmNHl0w5N_XgL0n3I9PlFUP0THsR8U"
+    );
+
+    fn rules_found(scanner: &Scanner, contents: &str) -> Vec<&'static str> {
+        scanner
+            .scan("a.txt", contents)
+            .iter()
+            .map(|f| f.rule)
+            .collect()
+    }
+
+    fn scanner() -> Scanner {
+        Scanner::new(None).unwrap()
+    }
+
+    #[test]
+    fn every_rule_finds_its_secret() {
+        let s = scanner();
+        for (line, rule) in [
+            (format!("key = {}", AWS_KEY_ID), "aws-access-key-id"),
+            (
+                format!("aws_secret_access_key = {}", AWS_SECRET),
+                "aws-secret-access-key",
+            ),
+            (format!("export GH={}", GITHUB_TOKEN), "github-token"),
+            (
+                format!("-----BEGIN RSA {} KEY-----", "PRIVATE"),
+                "private-key",
+            ),
+            (format!("Authorization: Bearer {}", JWT), "jwt"),
+            (
+                "db_password = \"Xk9#mQ2$vL7p\"".to_string(),
+                "generic-password",
+            ),
+        ] {
+            assert_eq!(rules_found(&s, &line), [rule], "{}", line);
+        }
+    }
+
+    #[test]
+    fn keywords_are_matched_without_case() {
+        assert_eq!(
+            rules_found(&scanner(), "API_KEY: 'Zq8!rT3@wY6#uI9'"), // dringrep:allow
+            ["generic-password"]
+        );
+    }
+
+    #[test]
+    fn one_secret_is_one_finding() {
+        let line = format!("github_token = \"{}\"", GITHUB_TOKEN);
+        assert_eq!(rules_found(&scanner(), &line), ["github-token"]);
+    }
+
+    #[test]
+    fn placeholders_are_too_plain() {
+        let s = scanner();
+        for line in [
+            "password = \"changeme\"",
+            "password = \"aaaaaaaaaaaa\"",
+            "token: xxxxxxxxxxxxxxxx",
+            &format!("key = {}", concat!("AKIA", "AAAAAAAAAAAAAAAA")),
+        ] {
+            assert!(rules_found(&s, line).is_empty(), "{}", line);
+        }
+        assert!(entropy("aaaa") < 0.01);
+        assert!((entropy("abcd") - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn allowlist_entries() {
+        let line = format!("auth: Bearer {}\nkey = {}\n", JWT, AWS_KEY_ID);
+
+        let s = Scanner::new(Some("# comment\n\nrule:jwt\n")).unwrap();
+        assert_eq!(rules_found(&s, &line), ["aws-access-key-id"]);
+
+        let s = Scanner::new(Some("^key = ")).unwrap();
+        assert_eq!(rules_found(&s, &line), ["jwt"]);
+
+        let s = Scanner::new(Some("path:fixtures/**")).unwrap();
+        assert!(s.skip_paths.is_match("fixtures/keys/a.pem"));
+        assert!(!s.skip_paths.is_match("src/a.pem"));
+
+        let inline = format!("key = {} # {}", AWS_KEY_ID, INLINE_ALLOW);
+        assert!(rules_found(&scanner(), &inline).is_empty());
+
+        assert!(Scanner::new(Some("rule:no-such-rule")).is_err());
+        assert!(Scanner::new(Some("(unclosed")).is_err());
+    }
+
+    #[test]
+    fn fingerprints_ignore_the_line_number() {
+        let s = scanner();
+        let first = s.scan("a.txt", &format!("key = {}", AWS_KEY_ID));
+        let moved = s.scan("a.txt", &format!("\n\nkey = {}", AWS_KEY_ID));
+        let elsewhere = s.scan("b.txt", &format!("key = {}", AWS_KEY_ID));
+        assert_eq!(first[0].fingerprint, moved[0].fingerprint);
+        assert_ne!(first[0].fingerprint, elsewhere[0].fingerprint);
+        assert!(!first[0].fingerprint.contains(&AWS_KEY_ID[4..]));
+        assert_eq!(redact(AWS_KEY_ID), "AKIA****************");
+    }
+
+    #[test]
+    fn baselined_findings_are_not_new() {
+        let dir = std::env::temp_dir().join(format!("dringrep-secrets-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let baseline = dir.join("baseline.json");
+        fs::write(dir.join("config.env"), format!("AWS_KEY={}\n", AWS_KEY_ID)).unwrap();
+
+        let summary = scan_secrets(&dir, None, Some(&baseline), false, 2, true).unwrap();
+        assert_eq!((summary.new, summary.baselined), (1, 0));
+
+        let summary = scan_secrets(&dir, None, Some(&baseline), true, 2, true).unwrap();
+        assert_eq!(summary.baselined, 1);
+        let summary = scan_secrets(&dir, None, Some(&baseline), false, 2, true).unwrap();
+        assert_eq!((summary.new, summary.baselined), (0, 1));
+        // the baseline holds fingerprints, not the secret
+        assert!(!fs::read_to_string(&baseline).unwrap().contains(AWS_KEY_ID));
+
+        // a new secret next to the known one is reported
+        fs::write(
+            dir.join("config.env"),
+            format!("\nAWS_KEY={}\nGH={}\n", AWS_KEY_ID, GITHUB_TOKEN),
+        )
+        .unwrap();
+        let summary = scan_secrets(&dir, None, Some(&baseline), false, 2, true).unwrap();
+        assert_eq!((summary.new, summary.baselined), (1, 1));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
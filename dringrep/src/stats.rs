@@ -0,0 +1,183 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde_json::json;
+
+use crate::WorkerStats;
+
+// counters for --stats, shared by all workers through the config
+#[derive(Default)]
+pub struct Stats {
+    pub files_considered: AtomicU64,
+    pub files_searched: AtomicU64,
+    pub skipped_binary: AtomicU64,
+    pub skipped_filter: AtomicU64,
+    pub skipped_error: AtomicU64,
+    pub skipped_hidden: AtomicU64,
+    pub skipped_too_large: AtomicU64,
+    pub skipped_loop: AtomicU64,
+    pub skipped_indexed: AtomicU64,
+    pub skipped_filesystem: AtomicU64,
+    pub skipped_depth: AtomicU64,
+    pub skipped_symlink: AtomicU64,
+    pub bytes_read: AtomicU64,
+    pub matched_lines: AtomicU64,
+    pub total_matches: AtomicU64,
+    // handed over by the pool before it shuts down
+    pub workers: Mutex<Vec<Arc<WorkerStats>>>,
+}
+
+pub enum SkipReason {
+    Binary,
+    Filter,
+    Error,
+    Hidden,
+    // over --max-filesize
+    TooLarge,
+    // a followed symlink pointing back at one of its ancestors
+    Loop,
+    // unchanged since `index build` and without the query's trigrams
+    Indexed,
+    // a directory on another filesystem, with --one-file-system
+    OtherFilesystem,
+    // a directory whose entries are past --max-depth
+    TooDeep,
+    // a symlink, without --follow
+    Symlink,
+}
+
+impl SkipReason {
+    pub fn label(&self) -> &'static str {
+        match self {
+            SkipReason::Binary => "binary",
+            SkipReason::Filter => "filtered",
+            SkipReason::Error => "error",
+            SkipReason::Hidden => "hidden",
+            SkipReason::TooLarge => "too large",
+            SkipReason::Loop => "symlink loop",
+            SkipReason::Indexed => "ruled out by index",
+            SkipReason::OtherFilesystem => "other filesystem",
+            SkipReason::TooDeep => "too deep",
+            SkipReason::Symlink => "symlink",
+        }
+    }
+}
+
+impl Stats {
+    pub fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn skipped(&self, reason: SkipReason) {
+        let counter = match reason {
+            SkipReason::Binary => &self.skipped_binary,
+            SkipReason::Filter => &self.skipped_filter,
+            SkipReason::Error => &self.skipped_error,
+            SkipReason::Hidden => &self.skipped_hidden,
+            SkipReason::TooLarge => &self.skipped_too_large,
+            SkipReason::Loop => &self.skipped_loop,
+            SkipReason::Indexed => &self.skipped_indexed,
+            SkipReason::OtherFilesystem => &self.skipped_filesystem,
+            SkipReason::TooDeep => &self.skipped_depth,
+            SkipReason::Symlink => &self.skipped_symlink,
+        };
+        Stats::add(counter, 1);
+    }
+
+    pub fn set_workers(&self, workers: Vec<Arc<WorkerStats>>) {
+        *self.workers.lock().unwrap() = workers;
+    }
+
+    pub fn print(&self, elapsed: Duration, as_json: bool) {
+        let get = |c: &AtomicU64| c.load(Ordering::Relaxed);
+        let workers = self.workers.lock().unwrap();
+
+        let secs = elapsed.as_secs_f64();
+        let bytes = get(&self.bytes_read);
+        let searched = get(&self.files_searched);
+        // avoid dividing by zero on very fast runs
+        let (mb_per_sec, files_per_sec) = if secs > 0.0 {
+            (bytes as f64 / 1_000_000.0 / secs, searched as f64 / secs)
+        } else {
+            (0.0, 0.0)
+        };
+
+        if as_json {
+            let summary = json!({
+                "type": "summary",
+                "files_considered": get(&self.files_considered),
+                "files_searched": searched,
+                "files_skipped": {
+                    "binary": get(&self.skipped_binary),
+                    "filter": get(&self.skipped_filter),
+                    "error": get(&self.skipped_error),
+                    "hidden": get(&self.skipped_hidden),
+                    "too_large": get(&self.skipped_too_large),
+                    "loop": get(&self.skipped_loop),
+                    "indexed": get(&self.skipped_indexed),
+                    "other_filesystem": get(&self.skipped_filesystem),
+                    "too_deep": get(&self.skipped_depth),
+                    "symlink": get(&self.skipped_symlink),
+                },
+                "bytes_read": bytes,
+                "matched_lines": get(&self.matched_lines),
+                "total_matches": get(&self.total_matches),
+                "elapsed_secs": secs,
+                "mb_per_sec": mb_per_sec,
+                "files_per_sec": files_per_sec,
+                "workers": workers
+                    .iter()
+                    .enumerate()
+                    .map(|(id, w)| json!({
+                        "id": id,
+                        "jobs": get(&w.jobs),
+                        "busy_secs": get(&w.busy_nanos) as f64 / 1e9,
+                        "panics": get(&w.panics),
+                    }))
+                    .collect::<Vec<_>>(),
+            });
+            println!("{}", summary);
+            return;
+        }
+
+        println!();
+        println!("{} files considered", get(&self.files_considered));
+        println!("{} files searched", searched);
+        let skipped = [
+            (get(&self.skipped_binary), "binary"),
+            (get(&self.skipped_filter), "filtered"),
+            (get(&self.skipped_error), "errors"),
+            (get(&self.skipped_hidden), "hidden"),
+            (get(&self.skipped_too_large), "too large"),
+            (get(&self.skipped_loop), "symlink loops"),
+            (get(&self.skipped_indexed), "ruled out by index"),
+            (get(&self.skipped_filesystem), "on other filesystems"),
+            (get(&self.skipped_depth), "too deep"),
+            (get(&self.skipped_symlink), "symlinks"),
+        ];
+        let breakdown: Vec<String> = skipped
+            .iter()
+            .map(|(n, label)| format!("{} {}", n, label))
+            .collect();
+        println!(
+            "{} entries skipped ({})",
+            skipped.iter().map(|(n, _)| n).sum::<u64>(),
+            breakdown.join(", ")
+        );
+        println!("{} bytes read", bytes);
+        println!("{} matched lines", get(&self.matched_lines));
+        println!("{} matches", get(&self.total_matches));
+        println!("{:.6} seconds elapsed", secs);
+        println!("{:.2} MB/s, {:.0} files/s", mb_per_sec, files_per_sec);
+        for (id, w) in workers.iter().enumerate() {
+            println!(
+                "worker {}: {} jobs, {:.6}s busy, {} panics",
+                id,
+                get(&w.jobs),
+                get(&w.busy_nanos) as f64 / 1e9,
+                get(&w.panics)
+            );
+        }
+    }
+}
@@ -0,0 +1,312 @@
+/*
+--csv / --tsv: search delimited files record by record instead of line by line.
+
+The first record is the header. The pattern is only tried on the fields picked with
+--field (all of them by default), so a hit in the wrong column is no hit. Quoted fields may
+hold delimiters and newlines; a record that spans several lines is still one result,
+reported at the line and column of its first hit. Each result names the field(s) that
+matched, then the row:
+
+    email: 12,bob@example.com,"Smith, Bob"
+
+--print-fields trims the printed row to the given fields.
+*/
+
+use std::borrow::Cow;
+
+use csv::{ReaderBuilder, StringRecord};
+
+use crate::{Config, LineMatch, Matcher, Span, Stats, paint_spans, redact_spans};
+
+// a field by header name or by 1-based position, as `cut -f` counts
+#[derive(Clone)]
+pub enum FieldRef {
+    Name(String),
+    Index(usize),
+}
+
+impl FieldRef {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s.parse::<usize>() {
+            Ok(0) => Err("--field and --print-fields count from 1".to_string()),
+            Ok(i) => Ok(FieldRef::Index(i)),
+            Err(_) => Ok(FieldRef::Name(s.to_string())),
+        }
+    }
+
+    fn resolve(&self, header: &StringRecord) -> Option<usize> {
+        match self {
+            FieldRef::Name(name) => header.iter().position(|h| h == name),
+            FieldRef::Index(i) => (*i <= header.len()).then(|| i - 1),
+        }
+    }
+}
+
+pub struct Table {
+    pub delimiter: u8,
+    // fields the pattern is tried on, empty for all
+    pub fields: Vec<FieldRef>,
+    // fields printed, empty for all
+    pub print: Vec<FieldRef>,
+}
+
+// the fields a list of refs names in this file, in order; an empty list names every field.
+// Names a file doesn't have are left out, so one --field works across files that differ.
+fn columns(refs: &[FieldRef], header: &StringRecord) -> Vec<usize> {
+    if refs.is_empty() {
+        return (0..header.len()).collect();
+    }
+    refs.iter().filter_map(|r| r.resolve(header)).collect()
+}
+
+// one field as it appears in the printed row: newlines escaped so the row stays on one
+// line, quoted when it holds the delimiter or a quote
+fn render_field(value: &str, delimiter: u8) -> String {
+    let value = value.replace("\r\n", "\\n").replace('\n', "\\n");
+    if value.contains(delimiter as char) || value.contains('"') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+// where byte `at` of field `field`'s value is in the raw record: the fields before it are
+// skipped delimiter by delimiter, and inside a quoted field `""` is one byte of the value
+fn raw_offset(raw: &str, delimiter: u8, field: usize, at: usize) -> usize {
+    let bytes = raw.as_bytes();
+    let mut i = 0;
+    let mut seen = 0;
+    let mut quoted = false;
+    while seen < field && i < bytes.len() {
+        match bytes[i] {
+            b'"' => quoted = !quoted,
+            b if b == delimiter && !quoted => seen += 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    if bytes.get(i) != Some(&b'"') {
+        return (i + at).min(raw.len());
+    }
+    i += 1;
+    for _ in 0..at {
+        if i >= bytes.len() {
+            break;
+        }
+        i += if bytes[i] == b'"' && bytes.get(i + 1) == Some(&b'"') {
+            2
+        } else {
+            1
+        };
+    }
+    i
+}
+
+// line and column of `at` in the file, counting from where the record starts
+fn position_in(raw: &str, at: usize, line: usize) -> (usize, usize) {
+    let before = &raw[..at];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        line + before.matches('\n').count(),
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+// `contents` starts at line `first_line + 1` and byte `first_byte`, like search_chunk
+pub fn search_table<'a>(
+    config: &Config,
+    table: &Table,
+    contents: &'a str,
+    first_line: usize,
+    first_byte: usize,
+) -> Vec<LineMatch<'a>> {
+    let query = &config.pattern;
+    let stats = config.stats.as_ref();
+    let mut reader = ReaderBuilder::new()
+        .delimiter(table.delimiter)
+        .flexible(true)
+        .from_reader(contents.as_bytes());
+
+    let Ok(header) = reader.headers().cloned() else {
+        return Vec::new();
+    };
+    let searched = columns(&table.fields, &header);
+    let printed = columns(&table.print, &header);
+
+    let mut results = Vec::new();
+    let mut record = StringRecord::new();
+    // a malformed record ends the search of the file
+    while let Ok(true) = reader.read_record(&mut record) {
+        let Some(position) = record.position() else {
+            continue;
+        };
+        let start = position.byte() as usize;
+        let raw = &contents[start..reader.position().byte() as usize];
+        let line = first_line + position.line() as usize;
+
+        let hits: Vec<usize> = searched
+            .iter()
+            .copied()
+            .filter(|&i| {
+                record
+                    .get(i)
+                    .is_some_and(|v| query.selects_line(v, config.file_scope))
+            })
+            .collect();
+        if hits.is_empty() ^ config.invert {
+            continue;
+        }
+
+        // the row, with the spans of every hit in a matched field that is printed
+        let mut text = String::new();
+        let mut spans = Vec::new();
+        if !config.invert {
+            let names: Vec<&str> = hits.iter().map(|&i| &header[i]).collect();
+            text.push_str(&names.join(","));
+            text.push_str(": ");
+        }
+        for (n, &i) in printed.iter().enumerate() {
+            if n > 0 {
+                text.push(table.delimiter as char);
+            }
+            let field = render_field(record.get(i).unwrap_or(""), table.delimiter);
+            if hits.contains(&i) {
+                spans.extend(query.find_all(&field).into_iter().map(|s| Span {
+                    start: s.start + text.len(),
+                    end: s.end + text.len(),
+                    pattern: s.pattern,
+                }));
+            }
+            text.push_str(&field);
+        }
+
+        // where the first hit is in the file, inside the raw text of its field
+        let first_hit = hits
+            .first()
+            .and_then(|&i| {
+                let span = query.find_all(record.get(i)?).into_iter().next()?;
+                Some(raw_offset(raw, table.delimiter, i, span.start))
+            })
+            .unwrap_or(0);
+        let (line_number, column) = position_in(raw, first_hit, line);
+
+        if let Some(stats) = stats {
+            Stats::add(&stats.matched_lines, 1);
+            Stats::add(&stats.total_matches, spans.len().max(1) as u64);
+        }
+
+        // -o: the matched text of each hit, as for lines
+        if config.only_matching {
+            for &i in &hits {
+                let value = record.get(i).unwrap_or("");
+                for s in query
+                    .find_all(value)
+                    .into_iter()
+                    .filter(|s| s.start < s.end)
+                {
+                    let hit = &value[s.start..s.end];
+                    let text = if config.highlight {
+                        config.colors.for_pattern(s.pattern).paint(hit).to_string()
+                    } else {
+                        hit.to_string()
+                    };
+                    results.push(LineMatch {
+                        line_number,
+                        column,
+                        byte_offset: first_byte + start + first_hit,
+                        spans: vec![Span {
+                            start: 0,
+                            end: hit.len(),
+                            pattern: s.pattern,
+                        }],
+                        text: Cow::Owned(text),
+                    });
+                }
+            }
+            continue;
+        }
+
+        let (text, spans) = if config.redact {
+            redact_spans(&text, &spans)
+        } else {
+            (text, spans)
+        };
+        let painted = if config.highlight {
+            paint_spans(&text, &spans, &config.colors)
+        } else {
+            text
+        };
+        results.push(LineMatch {
+            line_number,
+            column,
+            byte_offset: first_byte + start + first_hit,
+            spans,
+            text: Cow::Owned(painted),
+        });
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Args;
+    use clap::Parser;
+
+    fn config(args: &[&str]) -> Config {
+        let args = Args::parse_from(["dringrep", "--csv"].iter().chain(args).copied());
+        Config::try_from(args).unwrap()
+    }
+
+    // (line, column, byte offset) of every result
+    fn positions(args: &[&str], contents: &str) -> Vec<(usize, usize, usize)> {
+        let config = config(args);
+        search_table(&config, config.table.as_ref().unwrap(), contents, 0, 0)
+            .iter()
+            .map(|m| (m.line_number, m.column, m.byte_offset))
+            .collect()
+    }
+
+    #[test]
+    fn hit_is_placed_in_its_own_field() {
+        // the same text sits in an earlier column that isn't searched
+        let csv = "a,b\nbob,bob\n";
+        assert_eq!(
+            positions(&["--query", "bob", "--field", "b"], csv),
+            [(2, 5, 8)]
+        );
+    }
+
+    #[test]
+    fn quoted_field_with_a_newline() {
+        let csv = "id,note\n1,\"first line\nsecond \"\"bob\"\" line\"\n2,plain\n";
+        // bob is on the record's second line, after `second ""`
+        let bob = csv.find("bob").unwrap();
+        let line_start = csv[..bob].rfind('\n').unwrap() + 1;
+        assert_eq!(
+            positions(&["--query", "bob"], csv),
+            [(3, bob - line_start + 1, bob)]
+        );
+        // the next record still starts where it should
+        assert_eq!(
+            positions(&["--query", "plain"], csv),
+            [(4, 3, csv.find("plain").unwrap())]
+        );
+
+        let config = config(&["--query", "bob"]);
+        let found = search_table(&config, config.table.as_ref().unwrap(), csv, 0, 0);
+        assert_eq!(
+            &*found[0].text,
+            "note: 1,\"first line\\nsecond \"\"bob\"\" line\""
+        );
+    }
+
+    #[test]
+    fn raw_offsets_count_quotes() {
+        let raw = "x,\"a,\"\"b\"\"\",c";
+        // field 1 is `a,"b"`, its `b` at byte 3
+        assert_eq!(&raw[raw_offset(raw, b',', 1, 3)..][..1], "b");
+        assert_eq!(raw_offset(raw, b',', 2, 0), raw.len() - 1);
+        assert_eq!(raw_offset(raw, b',', 0, 0), 0);
+    }
+}
@@ -0,0 +1,456 @@
+/*
+--since / --until: only search the log lines written inside a time window.
+
+A line's time is read from its start: RFC 3339 / ISO 8601 (`2024-05-01T12:00:00Z`,
+`2024-05-01 12:00:00,123`), syslog (`May  1 12:00:00`, taken to be in the last twelve
+months) or the bracketed time of the Apache/Nginx common log format
+(`[10/Oct/2000:13:55:36 -0700]`). --time-format replaces the guessing with one strftime
+format. Times without an offset are local time. Lines without a time (stack traces,
+wrapped messages) belong to the line above them.
+
+When a file looks sorted by time, the window's start and end are found by binary search,
+so the lines before and after it are never parsed for a time or matched. The file is still
+read whole, and the lines before the window are counted for line numbers: what is saved is
+the per-line work, not the I/O.
+*/
+
+use chrono::{
+    DateTime, Datelike, Duration, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Utc,
+};
+
+use crate::{Config, LineMatch, process_lines};
+
+// smaller files are just filtered line by line
+const BISECT_MIN: usize = 64 * 1024;
+// lines looked at in several places to guess whether a file is sorted
+const SORT_SAMPLES: usize = 16;
+
+pub struct TimeWindow {
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    // --time-format, instead of guessing
+    pub format: Option<String>,
+}
+
+// `15m`, `2h`, `3d` ago, an RFC 3339 time, or a local `YYYY-MM-DD[ HH:MM[:SS]]`
+pub fn parse_when(when: &str) -> Result<DateTime<Utc>, String> {
+    let invalid = || {
+        format!(
+            "Invalid time `{}`: expected e.g. 30m, 2h, 1d, 2024-05-01, 2024-05-01 12:00 or an RFC 3339 time",
+            when
+        )
+    };
+    let when = when.trim();
+
+    if let Some(unit) = when.chars().last()
+        && let Ok(n) = when[..when.len() - unit.len_utf8()].parse::<i64>()
+    {
+        let ago = match unit {
+            's' => Duration::try_seconds(n),
+            'm' => Duration::try_minutes(n),
+            'h' => Duration::try_hours(n),
+            'd' => Duration::try_days(n),
+            _ => return Err(invalid()),
+        };
+        // too far back for a date is as invalid as a typo
+        return ago
+            .and_then(|ago| Utc::now().checked_sub_signed(ago))
+            .ok_or_else(invalid);
+    }
+
+    if let Ok(time) = DateTime::parse_from_rfc3339(when) {
+        return Ok(time.to_utc());
+    }
+    let naive = [
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M",
+    ]
+    .iter()
+    .find_map(|f| NaiveDateTime::parse_from_str(when, f).ok())
+    .or_else(|| {
+        NaiveDate::parse_from_str(when, "%Y-%m-%d")
+            .ok()
+            .and_then(|d| d.and_hms_opt(0, 0, 0))
+    })
+    .ok_or_else(invalid)?;
+    local(naive).ok_or_else(invalid)
+}
+
+fn local(naive: NaiveDateTime) -> Option<DateTime<Utc>> {
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|t| t.to_utc())
+}
+
+impl TimeWindow {
+    // the time at the start of `line`, if it has one
+    fn timestamp(&self, line: &str) -> Option<DateTime<Utc>> {
+        if let Some(format) = &self.format {
+            return DateTime::<FixedOffset>::parse_and_remainder(line, format)
+                .map(|(t, _)| t.to_utc())
+                .ok()
+                .or_else(|| {
+                    let (naive, _) = NaiveDateTime::parse_and_remainder(line, format).ok()?;
+                    local(naive)
+                });
+        }
+
+        let first = line.chars().next()?;
+        // a date is all digits and dashes: 2024-05-01
+        let dated = line
+            .as_bytes()
+            .get(..10)
+            .is_some_and(|date| date.iter().all(|&b| b.is_ascii_digit() || b == b'-'));
+        if dated {
+            if let Ok((time, _)) =
+                DateTime::<FixedOffset>::parse_and_remainder(line, "%Y-%m-%dT%H:%M:%S%.f%#z")
+            {
+                return Some(time.to_utc());
+            }
+            let (naive, _) = NaiveDateTime::parse_and_remainder(line, "%Y-%m-%dT%H:%M:%S%.f")
+                .or_else(|_| NaiveDateTime::parse_and_remainder(line, "%Y-%m-%d %H:%M:%S%.f"))
+                .ok()?;
+            return local(naive);
+        }
+        if first.is_ascii_uppercase() {
+            return syslog_time(line);
+        }
+
+        // common log format: host ident user [time] "request" ...
+        let open = line.find('[')?;
+        if line[..open].contains('"') {
+            return None;
+        }
+        DateTime::<FixedOffset>::parse_and_remainder(&line[open + 1..], "%d/%b/%Y:%H:%M:%S %z")
+            .map(|(t, _)| t.to_utc())
+            .ok()
+    }
+
+    fn contains(&self, time: DateTime<Utc>) -> bool {
+        self.since.is_none_or(|since| time >= since) && self.until.is_none_or(|until| time <= until)
+    }
+
+    // the searched part of `contents`, cut into the runs of lines inside the window
+    pub fn search<'a>(
+        &self,
+        config: &Config,
+        contents: &'a str,
+        first_line: usize,
+        first_byte: usize,
+    ) -> Vec<LineMatch<'a>> {
+        let (start, end) = if contents.len() >= BISECT_MIN && self.sorted(contents) {
+            let start = match self.since {
+                Some(since) => self.bisect(contents, |t| t >= since),
+                None => 0,
+            };
+            let end = match self.until {
+                Some(until) => self.bisect(contents, |t| t > until),
+                None => contents.len(),
+            };
+            (start, end.max(start))
+        } else {
+            (0, contents.len())
+        };
+
+        let mut results = Vec::new();
+        let lines_before = first_line + contents[..start].matches('\n').count();
+        let mut time = None;
+        // where the current run of lines inside the window started, and its first line
+        let mut run: Option<(usize, usize)> = None;
+        let mut offset = start;
+
+        for (line, raw) in (lines_before..).zip(contents[start..end].split_inclusive('\n')) {
+            if let Some(t) = self.timestamp(raw) {
+                time = Some(t);
+            }
+            let inside = time.is_some_and(|t| self.contains(t));
+            match (inside, run) {
+                (true, None) => run = Some((offset, line)),
+                (false, Some((run_start, run_line))) => {
+                    results.extend(process_lines(
+                        config,
+                        &contents[run_start..offset],
+                        run_line,
+                        first_byte + run_start,
+                    ));
+                    run = None;
+                }
+                _ => {}
+            }
+            offset += raw.len();
+        }
+        if let Some((run_start, run_line)) = run {
+            results.extend(process_lines(
+                config,
+                &contents[run_start..end],
+                run_line,
+                first_byte + run_start,
+            ));
+        }
+        results
+    }
+
+    // the first time at or after byte `at`, and where its line starts
+    fn time_from(&self, contents: &str, at: usize) -> Option<(DateTime<Utc>, usize)> {
+        let mut start = line_start(contents, at);
+        for raw in contents[start..].split_inclusive('\n') {
+            if let Some(time) = self.timestamp(raw) {
+                return Some((time, start));
+            }
+            start += raw.len();
+        }
+        None
+    }
+
+    // a few times spread over the file, all in order
+    fn sorted(&self, contents: &str) -> bool {
+        let step = contents.len() / SORT_SAMPLES;
+        let times: Vec<DateTime<Utc>> = (0..SORT_SAMPLES)
+            .filter_map(|i| self.time_from(contents, i * step))
+            .map(|(t, _)| t)
+            .collect();
+        times.len() > 1 && times.windows(2).all(|w| w[0] <= w[1])
+    }
+
+    // start of the first timed line whose time satisfies `past`, or the end if none does;
+    // `past` has to turn true once and stay true, which a sorted file guarantees.
+    // Untimed lines just before it belong to the line above, so they are left out.
+    fn bisect(&self, contents: &str, past: impl Fn(DateTime<Utc>) -> bool) -> usize {
+        let (mut lo, mut hi) = (0, contents.len());
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            match self.time_from(contents, mid) {
+                Some((time, _)) if !past(time) => lo = mid + 1,
+                _ => hi = mid,
+            }
+        }
+        self.time_from(contents, lo)
+            .map_or(contents.len(), |(_, start)| start)
+    }
+}
+
+// start of the first line at or after byte `at`
+fn line_start(contents: &str, at: usize) -> usize {
+    if at == 0 {
+        return 0;
+    }
+    match contents.as_bytes()[at - 1..]
+        .iter()
+        .position(|&b| b == b'\n')
+    {
+        Some(i) => at + i,
+        None => contents.len(),
+    }
+}
+
+// syslog leaves the year out: the last twelve months are assumed
+fn syslog_time(line: &str) -> Option<DateTime<Utc>> {
+    let now = Local::now();
+    let stamp = format!("{} {}", now.year(), line.get(..15)?);
+    let naive = NaiveDateTime::parse_from_str(&stamp, "%Y %b %e %H:%M:%S").ok()?;
+    let time = local(naive)?;
+    if now
+        .to_utc()
+        .checked_add_signed(Duration::days(1))
+        .is_some_and(|tomorrow| time > tomorrow)
+    {
+        let naive = naive.with_year(now.year() - 1)?;
+        return local(naive);
+    }
+    Some(time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Args;
+    use clap::Parser;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    fn window(since: Option<&str>, until: Option<&str>) -> TimeWindow {
+        TimeWindow {
+            since: since.map(at),
+            until: until.map(at),
+            format: None,
+        }
+    }
+
+    fn config() -> Config {
+        Config::try_from(Args::parse_from(["dringrep", "--query", "x"])).unwrap()
+    }
+
+    // one event a second from midnight, each followed by an untimed continuation line;
+    // big enough to be bisected
+    fn sorted_log(events: usize) -> String {
+        let start = at("2024-05-01T00:00:00Z");
+        let mut log = String::new();
+        for i in 0..events {
+            let time = start + Duration::seconds(i as i64);
+            log.push_str(&format!(
+                "{} event {} x\n  at frame x\n",
+                time.to_rfc3339(),
+                i
+            ));
+        }
+        assert!(log.len() >= BISECT_MIN);
+        log
+    }
+
+    fn event(log: &str, line_start: usize) -> &str {
+        log[line_start..].lines().next().unwrap()
+    }
+
+    #[test]
+    fn relative_times_count_back_from_now() {
+        let before = Utc::now();
+        let since = parse_when("30m").unwrap();
+        let after = Utc::now();
+        assert!(since >= before - Duration::minutes(30));
+        assert!(since <= after - Duration::minutes(30));
+        assert!(parse_when("2d").unwrap() < parse_when("47h").unwrap());
+    }
+
+    #[test]
+    fn absolute_times() {
+        assert_eq!(
+            parse_when("2024-05-01T12:00:00+02:00").unwrap(),
+            at("2024-05-01T10:00:00Z")
+        );
+        let local_midnight = Local
+            .with_ymd_and_hms(2024, 5, 1, 0, 0, 0)
+            .earliest()
+            .unwrap()
+            .to_utc();
+        assert_eq!(parse_when("2024-05-01").unwrap(), local_midnight);
+        assert_eq!(
+            parse_when("2024-05-01 00:00").unwrap(),
+            parse_when("2024-05-01T00:00:00").unwrap()
+        );
+    }
+
+    #[test]
+    fn invalid_and_overflowing_times_are_errors() {
+        for bad in [
+            "",
+            "5w",
+            "m",
+            "yesterday",
+            "2024-13-01",
+            "99999999999999d",
+            "9999999999999999s",
+            "99999999999999999999h",
+        ] {
+            assert!(parse_when(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn timestamp_formats() {
+        let w = window(None, None);
+        let t = at("2000-10-10T20:55:36Z");
+        assert_eq!(w.timestamp("2000-10-10T20:55:36Z info"), Some(t));
+        assert_eq!(
+            w.timestamp("2000-10-10T22:55:36.250+02:00 x"),
+            Some(t + Duration::milliseconds(250))
+        );
+        assert_eq!(
+            w.timestamp(r#"127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] "GET / HTTP/1.0" 200"#),
+            Some(t)
+        );
+        assert_eq!(w.timestamp("  at frame"), None);
+        assert_eq!(
+            w.timestamp(r#"1.2.3.4 "GET [10/Oct/2000:13:55:36 -0700]""#),
+            None
+        );
+
+        let custom = TimeWindow {
+            format: Some("%d.%m.%Y %H:%M:%S %z".to_string()),
+            ..window(None, None)
+        };
+        assert_eq!(custom.timestamp("10.10.2000 20:55:36 +0000 x"), Some(t));
+        assert_eq!(custom.timestamp("2000-10-10T20:55:36Z"), None);
+    }
+
+    #[test]
+    fn bisect_finds_the_window_bounds() {
+        let log = sorted_log(2000);
+        let w = window(None, None);
+        assert!(w.sorted(&log));
+
+        let since = at("2024-05-01T00:10:00Z");
+        let start = w.bisect(&log, |t| t >= since);
+        assert!(event(&log, start).starts_with("2024-05-01T00:10:00+00:00 event 600 "));
+
+        // past the end of an event: the next timed line, not its continuation
+        let until = at("2024-05-01T00:20:00Z");
+        let end = w.bisect(&log, |t| t > until);
+        assert!(event(&log, end).contains(" event 1201 "));
+
+        // before the first line and after the last
+        assert_eq!(w.bisect(&log, |t| t >= at("2020-01-01T00:00:00Z")), 0);
+        assert_eq!(
+            w.bisect(&log, |t| t >= at("2030-01-01T00:00:00Z")),
+            log.len()
+        );
+    }
+
+    #[test]
+    fn sorted_search_keeps_continuation_lines() {
+        let log = sorted_log(2000);
+        let w = window(Some("2024-05-01T00:10:00Z"), Some("2024-05-01T00:20:00Z"));
+        let found = w.search(&config(), &log, 0, 0);
+
+        // 601 events and their continuation lines
+        assert_eq!(found.len(), 2 * 601);
+        assert_eq!(found[0].line_number, 2 * 600 + 1);
+        assert!(found[0].text.contains(" event 600 "));
+        assert!(found.last().unwrap().text.contains("at frame"));
+        assert_eq!(found[1].line_number, found[0].line_number + 1);
+    }
+
+    #[test]
+    fn unsorted_files_are_filtered_line_by_line() {
+        // the same events, every other one moved an hour back
+        let mut log = String::new();
+        for i in 0..2000 {
+            let hour = if i % 2 == 0 { "01" } else { "00" };
+            log.push_str(&format!(
+                "2024-05-01T{}:{:02}:{:02}Z event {} x\n  at frame x\n",
+                hour,
+                (i / 60) % 60,
+                i % 60,
+                i
+            ));
+        }
+        let w = window(Some("2024-05-01T01:00:00Z"), None);
+        assert!(!w.sorted(&log));
+
+        let found = w.search(&config(), &log, 0, 0);
+        assert_eq!(found.len(), 2 * 1000);
+        assert!(
+            found
+                .iter()
+                .filter(|m| m.text.contains("event"))
+                .all(|m| m.text.starts_with("2024-05-01T01:"))
+        );
+    }
+
+    #[test]
+    fn small_files_are_not_bisected() {
+        let log =
+            "2024-05-01T00:00:00Z a x\n2024-05-01T02:00:00Z b x\n  c x\n2024-05-01T01:00:00Z d x\n";
+        let w = window(Some("2024-05-01T00:30:00Z"), Some("2024-05-01T01:30:00Z"));
+        let found: Vec<usize> = w
+            .search(&config(), log, 10, 0)
+            .iter()
+            .map(|m| m.line_number)
+            .collect();
+        assert_eq!(found, [14]);
+    }
+}
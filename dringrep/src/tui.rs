@@ -0,0 +1,484 @@
+/*
+--tui: interactive search. Every edit of the query (or toggle of regex / icase / invert)
+cancels the running search and starts a new one from the command line's Args, so the
+TUI searches exactly like the non-interactive mode does.
+
+The screen is drawn on stderr, which leaves stdout free for the selected `path:line`:
+
+    vim $(dringrep --tui -r)
+*/
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, Stderr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+use ratatui::Terminal;
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{
+    EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode,
+};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{Frame, symbols};
+
+use crate::{
+    Args, CancelToken, ColorChoice, Config, Entry, FileResult, LineMatch, Matcher, ThreadPool,
+    process_batch, walk_parallel,
+};
+
+// more hits than anyone scrolls through; the search is cancelled once they are in
+const MAX_HITS: usize = 10_000;
+// results taken off the channel per frame, so typing stays responsive during a big search
+const DRAIN_PER_FRAME: usize = 2_000;
+
+struct Hit {
+    path: PathBuf,
+    line: LineMatch<'static>,
+}
+
+// one search in flight; cancelling it makes the pool skip every job still queued
+struct Run {
+    config: Arc<Config>,
+    cancel: CancelToken,
+    rx: Receiver<FileResult>,
+    done: bool,
+}
+
+enum Action {
+    None,
+    Quit,
+    Select(String),
+}
+
+struct App {
+    base: Args,
+    query: String,
+    regex: bool,
+    ignore_case: bool,
+    invert: bool,
+    run: Option<Run>,
+    hits: Vec<Hit>,
+    truncated: bool,
+    list: ListState,
+    error: Option<String>,
+    // lines of previewed files, so moving through hits in one file reads it once
+    preview_cache: HashMap<PathBuf, Vec<String>>,
+}
+
+// puts the terminal back even if the TUI returns early with an error or panics
+struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = execute!(io::stderr(), LeaveAlternateScreen);
+    }
+}
+
+// Ok(Some("path:line")) when a hit was picked with Enter, Ok(None) when the user quit
+pub fn run_tui(args: Args) -> io::Result<Option<String>> {
+    enable_raw_mode()?;
+    let _guard = TerminalGuard;
+    execute!(io::stderr(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stderr()))?;
+
+    let mut app = App {
+        query: args.query.clone().unwrap_or_default(),
+        regex: args.regex,
+        ignore_case: args.ignore_case,
+        invert: args.invert,
+        base: args,
+        run: None,
+        hits: Vec::new(),
+        truncated: false,
+        list: ListState::default(),
+        error: None,
+        preview_cache: HashMap::new(),
+    };
+    app.restart();
+
+    event_loop(&mut terminal, &mut app)
+}
+
+fn event_loop(
+    terminal: &mut Terminal<CrosstermBackend<Stderr>>,
+    app: &mut App,
+) -> io::Result<Option<String>> {
+    loop {
+        app.drain();
+        terminal.draw(|frame| app.draw(frame))?;
+
+        if !event::poll(Duration::from_millis(50))? {
+            continue;
+        }
+        if let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            match app.on_key(key) {
+                Action::None => {}
+                Action::Quit => {
+                    app.cancel();
+                    return Ok(None);
+                }
+                Action::Select(selected) => {
+                    app.cancel();
+                    return Ok(Some(selected));
+                }
+            }
+        }
+    }
+}
+
+// the same pipeline main runs: a single file is searched directly, anything else is walked
+fn spawn_search(config: Arc<Config>, cancel: CancelToken, tx: Sender<FileResult>) {
+    thread::spawn(move || {
+        let root = if config.file_path.is_empty() {
+            env::current_dir().unwrap_or_else(|_| PathBuf::from("."))
+        } else {
+            PathBuf::from(&config.file_path)
+        };
+
+        if root.is_file() {
+            // single_file = false: chunking the file would start a pool the token can't stop
+            let _ = process_batch(vec![Entry::new(root, 0)], tx, config, false);
+        } else {
+            let pool = ThreadPool::with_cancel_token(config.threads, cancel);
+            walk_parallel(root, config, pool.handle(), tx);
+            // waits for the walk to finish, which is quick once it is cancelled
+            drop(pool);
+        }
+    });
+}
+
+impl App {
+    fn cancel(&mut self) {
+        if let Some(run) = self.run.take() {
+            run.cancel.cancel();
+            // jobs still running keep sending; draining the old channel instead of closing
+            // it keeps their "failed to send" errors off the screen
+            thread::spawn(move || for _ in run.rx {});
+        }
+    }
+
+    fn restart(&mut self) {
+        self.cancel();
+        self.hits.clear();
+        self.truncated = false;
+        self.list.select(None);
+        self.error = None;
+
+        if self.query.is_empty() {
+            return;
+        }
+
+        let mut args = self.base.clone();
+        args.query = Some(self.query.clone());
+        args.regex = self.regex;
+        args.ignore_case = self.ignore_case;
+        args.invert = self.invert;
+        // the TUI styles matches itself, and anything printed would land on its screen
+        args.color = ColorChoice::Never;
+        args.highlight = false;
+        args.verbose = false;
+
+        let config = match Config::try_from(args) {
+            Ok(config) => Arc::new(config),
+            Err(e) => {
+                self.error = Some(e);
+                return;
+            }
+        };
+
+        let (tx, rx) = mpsc::channel();
+        let cancel = CancelToken::new();
+        spawn_search(Arc::clone(&config), cancel.clone(), tx);
+        self.run = Some(Run {
+            config,
+            cancel,
+            rx,
+            done: false,
+        });
+    }
+
+    fn drain(&mut self) {
+        let Some(run) = &mut self.run else {
+            return;
+        };
+
+        for _ in 0..DRAIN_PER_FRAME {
+            match run.rx.try_recv() {
+                Ok(FileResult::Match(path, lines)) => {
+                    for line in lines {
+                        if self.hits.len() == MAX_HITS {
+                            self.truncated = true;
+                            run.cancel.cancel();
+                            break;
+                        }
+                        self.hits.push(Hit {
+                            path: path.clone(),
+                            line,
+                        });
+                    }
+                }
+                Ok(FileResult::Error(e)) => self.error = Some(e),
+                Ok(_) => {}
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    run.done = true;
+                    break;
+                }
+            }
+        }
+
+        if self.list.selected().is_none() && !self.hits.is_empty() {
+            self.list.select(Some(0));
+        }
+    }
+
+    fn on_key(&mut self, key: KeyEvent) -> Action {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+
+        match key.code {
+            KeyCode::Esc => return Action::Quit,
+            KeyCode::Char('c') if ctrl => return Action::Quit,
+            KeyCode::Enter => {
+                if let (Some(hit), Some(run)) = (self.selected(), &self.run) {
+                    return Action::Select(format!(
+                        "{}:{}",
+                        run.config.display_path(&hit.path),
+                        hit.line.line_number
+                    ));
+                }
+            }
+            KeyCode::Char('r') if ctrl => {
+                self.regex = !self.regex;
+                self.restart();
+            }
+            KeyCode::Char('t') if ctrl => {
+                self.ignore_case = !self.ignore_case;
+                self.restart();
+            }
+            KeyCode::Char('v') if ctrl => {
+                self.invert = !self.invert;
+                self.restart();
+            }
+            KeyCode::Char('u') if ctrl => {
+                self.query.clear();
+                self.restart();
+            }
+            KeyCode::Char('n') if ctrl => self.move_selection(1),
+            KeyCode::Char('p') if ctrl => self.move_selection(-1),
+            KeyCode::Char(c) if !ctrl => {
+                self.query.push(c);
+                self.restart();
+            }
+            KeyCode::Backspace if self.query.pop().is_some() => self.restart(),
+            KeyCode::Down => self.move_selection(1),
+            KeyCode::Up => self.move_selection(-1),
+            KeyCode::PageDown => self.move_selection(20),
+            KeyCode::PageUp => self.move_selection(-20),
+            KeyCode::Home => self.move_selection(isize::MIN),
+            KeyCode::End => self.move_selection(isize::MAX),
+            _ => {}
+        }
+
+        Action::None
+    }
+
+    fn move_selection(&mut self, by: isize) {
+        if self.hits.is_empty() {
+            return;
+        }
+        let current = self.list.selected().unwrap_or(0) as isize;
+        let last = self.hits.len() as isize - 1;
+        let next = current.saturating_add(by).clamp(0, last);
+        self.list.select(Some(next as usize));
+    }
+
+    fn selected(&self) -> Option<&Hit> {
+        self.list.selected().and_then(|i| self.hits.get(i))
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [query_area, main_area, status_area] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [list_area, preview_area] =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                .areas(main_area);
+
+        let query = Paragraph::new(self.query.as_str()).block(Block::bordered().title(" query "));
+        frame.render_widget(query, query_area);
+        let cursor_x = query_area.x + 1 + self.query.chars().count() as u16;
+        frame.set_cursor_position((cursor_x.min(query_area.right() - 2), query_area.y + 1));
+
+        self.draw_list(frame, list_area);
+        self.draw_preview(frame, preview_area);
+        frame.render_widget(Paragraph::new(self.status_line()), status_area);
+    }
+
+    fn draw_list(&mut self, frame: &mut Frame, area: Rect) {
+        let Some(run) = &self.run else {
+            frame.render_widget(Block::bordered().title(" results "), area);
+            return;
+        };
+
+        let items: Vec<ListItem> = self
+            .hits
+            .iter()
+            .map(|hit| {
+                let mut spans = vec![
+                    Span::styled(
+                        run.config.display_path(&hit.path),
+                        Style::new().fg(Color::Green),
+                    ),
+                    Span::raw(format!(":{}: ", hit.line.line_number)),
+                ];
+                spans.extend(styled_line(&hit.line.text, |_| {
+                    hit.line.spans.iter().map(|s| (s.start, s.end)).collect()
+                }));
+                ListItem::new(Line::from(spans))
+            })
+            .collect();
+
+        let list = List::new(items)
+            .block(Block::bordered().title(" results "))
+            .highlight_style(Style::new().bg(Color::DarkGray))
+            .highlight_symbol(symbols::line::VERTICAL);
+        frame.render_stateful_widget(list, area, &mut self.list);
+    }
+
+    fn draw_preview(&mut self, frame: &mut Frame, area: Rect) {
+        let (Some(run), Some(i)) = (&self.run, self.list.selected()) else {
+            frame.render_widget(Block::bordered().title(" preview "), area);
+            return;
+        };
+        let Some(hit) = self.hits.get(i) else {
+            return;
+        };
+
+        if self.preview_cache.len() > 64 && !self.preview_cache.contains_key(&hit.path) {
+            self.preview_cache.clear();
+        }
+        let lines = self
+            .preview_cache
+            .entry(hit.path.clone())
+            .or_insert_with(|| match fs::read_to_string(&hit.path) {
+                Ok(contents) => contents.lines().map(str::to_string).collect(),
+                Err(e) => vec![format!("can't read {}: {}", hit.path.display(), e)],
+            });
+
+        // the hit sits in the middle, with as much context as fits around it
+        let height = area.height.saturating_sub(2) as usize;
+        let hit_index = hit.line.line_number.saturating_sub(1);
+        let first = hit_index.saturating_sub(height / 2);
+        let width = (first + height).to_string().len();
+
+        let text: Vec<Line> = lines
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(height)
+            .map(|(n, line)| {
+                let number_style = if n == hit_index {
+                    Style::new().fg(Color::Yellow).add_modifier(Modifier::BOLD)
+                } else {
+                    Style::new().fg(Color::DarkGray)
+                };
+                let mut spans = vec![Span::styled(
+                    format!("{:>width$} ", n + 1, width = width),
+                    number_style,
+                )];
+                spans.extend(styled_line(line, |l| {
+                    run.config
+                        .pattern
+                        .find_all(l)
+                        .iter()
+                        .map(|s| (s.start, s.end))
+                        .collect()
+                }));
+                Line::from(spans)
+            })
+            .collect();
+
+        let title = format!(" {} ", run.config.display_path(&hit.path));
+        frame.render_widget(
+            Paragraph::new(text).block(Block::bordered().title(title)),
+            area,
+        );
+    }
+
+    fn status_line(&self) -> Line<'_> {
+        let flag = |name: &str, key: &str, on: bool| {
+            let style = if on {
+                Style::new().fg(Color::Black).bg(Color::Cyan)
+            } else {
+                Style::new().fg(Color::DarkGray)
+            };
+            Span::styled(format!(" {} {} ", key, name), style)
+        };
+
+        let state = match &self.run {
+            _ if self.truncated => format!("{}+ hits (stopped)", self.hits.len()),
+            Some(run) if !run.done => format!("{} hits, searching...", self.hits.len()),
+            _ => format!("{} hits", self.hits.len()),
+        };
+
+        let mut spans = vec![
+            Span::raw(format!("{}  ", state)),
+            flag("regex", "^R", self.regex),
+            Span::raw(" "),
+            flag("icase", "^T", self.ignore_case),
+            Span::raw(" "),
+            flag("invert", "^V", self.invert),
+            Span::raw("  enter: print path:line  esc: quit"),
+        ];
+        if let Some(e) = &self.error {
+            // first line only, regex errors come with a multi-line caret diagram
+            let first = e.lines().next().unwrap_or_default();
+            spans.push(Span::styled(
+                format!("  {}", first),
+                Style::new().fg(Color::Red),
+            ));
+        }
+        Line::from(spans)
+    }
+}
+
+// splits a line into plain and matched pieces; tabs become spaces so columns stay put
+fn styled_line<'a>(line: &'a str, find: impl Fn(&str) -> Vec<(usize, usize)>) -> Vec<Span<'a>> {
+    let matched = Style::new().fg(Color::Red).add_modifier(Modifier::BOLD);
+    let clean = |s: &'a str| -> Span<'a> {
+        if s.contains('\t') {
+            Span::raw(s.replace('\t', " "))
+        } else {
+            Span::raw(s)
+        }
+    };
+
+    let mut spans = Vec::new();
+    let mut last = 0;
+    for (start, end) in find(line) {
+        if start < last || start == end || end > line.len() {
+            continue;
+        }
+        spans.push(clean(&line[last..start]));
+        spans.push(clean(&line[start..end]).style(matched));
+        last = end;
+    }
+    spans.push(clean(&line[last..]));
+    spans
+}
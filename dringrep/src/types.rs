@@ -1,26 +1,56 @@
-use std::collections::HashMap;
-use std::path::Path;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
 
-use clap::Parser;
-use crossbeam::channel::{Receiver, Sender, unbounded};
-use regex::{Regex, RegexBuilder};
+use chrono::{DateTime, Utc};
+
+use clap::{Parser, Subcommand, ValueEnum};
+use crossbeam::deque::{self, Injector, Stealer};
+use crossbeam::utils::Backoff;
+use regex::{Regex, RegexBuilder, RegexSet, RegexSetBuilder};
 use std::env;
-use std::process;
+use std::io::{self, IsTerminal};
 
 use aho_corasick::{AhoCorasick, AhoCorasickBuilder};
-use std::sync::{Arc, Mutex};
+
+use crate::{
+    ColorChoice, ColorSpecs, FieldRef, FuzzyMatcher, IndexFilter, JsonPath, JsonQuery,
+    Preprocessor, PresetMatcher, QueryExpr, Stats, Table, TimeWindow, parse_when,
+};
+#[cfg(feature = "pcre")]
+use crate::{PcreMatcher, pcre::DEFAULT_BACKTRACK_LIMIT};
+use std::any::Any;
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::thread;
+use std::time::{Duration, Instant};
 pub enum Pattern {
     Literal {
         pattern: AhoCorasick,
         case_insensitive: bool,
     },
     Regex(Regex),
+    // the set answers "does any pattern match" in one pass; the individual regexes are
+    // only run on lines the set accepted, to find spans and which pattern hit
+    MultipleRegex {
+        set: RegexSet,
+        regexes: Vec<Regex>,
+    },
     MultipleLiteral {
         pattern: AhoCorasick,
 
         case_insensitive: bool,
     },
+    // --expr: a boolean combination of literals and regexes
+    Expr(Box<QueryExpr>),
+    // --fuzzy: the query within a number of edits
+    Fuzzy(Box<FuzzyMatcher>),
+    // -P: backtracking engine for lookaround and backreferences
+    #[cfg(feature = "pcre")]
+    Pcre(Box<PcreMatcher>),
+    // --preset: built-in personal data patterns with checks on every hit
+    Preset(Box<PresetMatcher>),
     // AhoCorasick {
 
     // }
@@ -28,26 +58,117 @@ pub enum Pattern {
 
 // regex len needs some solution
 
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Default,
+    // path:line:col:text, one line per match
+    Vimgrep,
+    // path:line:col: text, what compilation-mode and M-x grep parse
+    Emacs,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum PathStyle {
+    // relative to the directory dringrep was started in
+    Relative,
+    Absolute,
+    // just the file name, like the old output
+    Name,
+}
+
 pub struct Config {
     pub file_path: String,
     pub pattern: Pattern,
+    // the source of every pattern, indexed like Span::pattern
+    pub pattern_names: Vec<String>,
     pub ignore_case: bool,
     pub invert: bool,
+    // evaluate --expr over the whole file instead of line by line
+    pub file_scope: bool,
     pub count: bool,
     pub line_number: bool,
     pub recursive: bool,
-    pub file_name_if_matches: bool,
+    pub files_with_matches: bool,
+    pub files_without_match: bool,
     pub file_extension: Option<String>,
     pub highlight: bool,
+    // print every match on its own line instead of the whole line
+    pub only_matching: bool,
+    // --redact: matches are masked in everything printed
+    pub redact: bool,
+    pub column: bool,
+    pub byte_offset: bool,
+    pub format: OutputFormat,
+    pub heading: bool,
+    pub path_style: PathStyle,
+    // end every printed path with a NUL byte, for xargs -0
+    pub null: bool,
+    pub max_depth: Option<usize>,
+    // with -r: follow symlinks; on a single file: keep reading what is appended (tail -f)
+    pub follow: bool,
+    // --follow starts at the top of the file instead of its end
+    pub from_start: bool,
+    pub hidden: bool,
+    pub one_file_system: bool,
+    pub max_filesize: Option<u64>,
+    // search the members of .tar, .tar.gz and .zip files as `archive!/member` files
+    pub search_archives: bool,
+    // --pre: files it applies to are searched through the converter's output
+    pub pre: Option<Preprocessor>,
+    // --csv / --tsv: files are searched record by record, only in the chosen fields
+    pub table: Option<Table>,
+    // --json-path: records are matched on the values at these paths only
+    pub json_path: Option<JsonQuery>,
+    // --since / --until: only lines timed inside the window are searched
+    pub time_window: Option<TimeWindow>,
+    // print every skipped entry and why to stderr
+    pub verbose: bool,
+    pub threads: usize,
+    pub color: bool,
+    pub colors: ColorSpecs,
+    // only allocated with --stats so the counters cost nothing otherwise
+    pub stats: Option<Stats>,
+    pub json: bool,
+    // --index: files it rules out are skipped unread
+    pub index: Option<IndexFilter>,
+    // keep running and print matches as files change
+    pub watch: bool,
 }
-pub struct Output {
-    pub output_map: HashMap<String, Vec<(usize, String)>>,
+
+impl Config {
+    // -l and -L only need a yes/no per file, so they can stop reading at the first hit
+    pub fn lists_files(&self) -> bool {
+        self.files_with_matches || self.files_without_match
+    }
+
+    pub fn display_path(&self, path: &Path) -> String {
+        let shown = match self.path_style {
+            PathStyle::Relative => env::current_dir()
+                .ok()
+                .and_then(|cwd| path.strip_prefix(cwd).ok().map(Path::to_path_buf))
+                .unwrap_or_else(|| path.to_path_buf()),
+            PathStyle::Absolute => std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()),
+            PathStyle::Name => path
+                .file_name()
+                .map(PathBuf::from)
+                .unwrap_or_else(|| path.to_path_buf()),
+        };
+        shown.to_string_lossy().into_owned()
+    }
+
+    // what follows a printed path: NUL with -0, the usual separator otherwise
+    pub fn path_terminator<'a>(&self, normal: &'a str) -> &'a str {
+        if self.null { "\0" } else { normal }
+    }
 }
-#[derive(Parser)]
+#[derive(Parser, Clone)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
     #[arg(long)]
     pub query: Option<String>,
-    #[arg(long, num_args = 1.., conflicts_with = "regex")]
+    // several patterns searched in one pass; with -E they are regexes
+    #[arg(long, num_args = 1..)]
     pub multiple: Vec<String>,
 
     #[arg(long = "icase")]
@@ -58,24 +179,223 @@ pub struct Args {
 
     #[arg(short, long)]
     pub invert: bool,
-    #[arg(short = 'E', long, conflicts_with = "multiple")]
+    #[arg(short = 'E', long)]
     pub regex: bool,
+    // regex with lookaround and backreferences on a backtracking engine (needs the `pcre` feature)
+    #[arg(short = 'P', long, requires = "query", conflicts_with_all = ["regex", "multiple", "fuzzy"])]
+    pub pcre: bool,
+    // backtracking steps one line may take with -P before it is given up on
+    #[arg(long, value_name = "STEPS", requires = "pcre")]
+    pub pcre_budget: Option<usize>,
+    // match the query with up to K insertions, deletions or substitutions
+    #[arg(long, value_name = "K", requires = "query", conflicts_with_all = ["regex", "multiple"])]
+    pub fuzzy: Option<usize>,
+    // boolean query, e.g. '(unsafe & unwrap) & !"SAFETY"'; words and "strings" are literals,
+    // /slashes/ are regexes
+    #[arg(long, value_name = "EXPR", conflicts_with_all = ["query", "multiple", "regex"])]
+    pub expr: Option<String>,
+    // built-in patterns for personal data, checked after matching: email, ipv4, ipv6,
+    // credit-card, iban, phone
+    #[arg(long, value_name = "NAME", value_delimiter = ',', conflicts_with_all = ["query", "multiple", "expr", "regex"])]
+    pub preset: Vec<String>,
+    // print every match masked with `*`, for output that may be shared
+    #[arg(long, conflicts_with = "only_matching")]
+    pub redact: bool,
+    // with --expr: a file matches if the expression holds for the file as a whole
+    #[arg(long, requires = "expr")]
+    pub file_scope: bool,
     #[arg(short = 'c', long)]
     pub count: bool,
-    #[arg(short, long)]
+    #[arg(short = 'n', long)]
     pub line_number: bool,
     #[arg(short = 'r', long)]
     pub recursive: bool,
-    #[arg(short = 'n', long)]
-    pub file_name_if_matches: bool,
+    // print only the paths of files with at least one match, one per line
+    #[arg(short = 'l', long, conflicts_with = "files_without_match")]
+    pub files_with_matches: bool,
+    // print only the paths of files without any match
+    #[arg(short = 'L', long)]
+    pub files_without_match: bool,
     #[arg(long, value_name = "EXTENSION")]
     // to use you pass cargo run -- --file-extension .rs
     pub file_extension: Option<String>,
     #[arg(long = "highlight")]
     pub highlight: bool,
+    // print only the matched part of each line, one match per output line
+    #[arg(short = 'o', long)]
+    pub only_matching: bool,
+    // print the 1-based column of the first match on each line
+    #[arg(long)]
+    pub column: bool,
+    // print the byte offset of the first match from the start of the file
+    #[arg(short = 'b', long)]
+    pub byte_offset: bool,
+    // path:line:col:text for every match, for vim's :cexpr / :grep
+    #[arg(long, conflicts_with = "emacs")]
+    pub vimgrep: bool,
+    // path:line:col: text, for Emacs compilation-mode / M-x grep
+    #[arg(long)]
+    pub emacs: bool,
+    // group lines under their file name; the default when printing to a terminal
+    #[arg(long, overrides_with = "no_heading")]
+    pub heading: bool,
+    // one self-contained `path: line` per result, the default when piped
+    #[arg(long)]
+    pub no_heading: bool,
+    #[arg(long, value_enum, default_value = "relative")]
+    pub path_style: PathStyle,
+    // terminate paths with NUL instead of ':' or newline, so they survive `xargs -0`
+    #[arg(short = '0', long)]
+    pub null: bool,
+    // 0 searches only the starting directory entry itself, 1 its direct children, ...
+    #[arg(long, value_name = "N")]
+    pub max_depth: Option<usize>,
+    // with -r: follow symlinks; loops are detected and skipped (-L is taken by --files-without-match)
+    // on a single file: keep reading appended lines like `tail -f`, surviving log rotation
+    #[arg(long)]
+    pub follow: bool,
+    // with --follow on a file: search the existing lines too, not just new ones
+    #[arg(long, requires = "follow")]
+    pub from_start: bool,
+    // skip dotfiles and don't descend into dot-directories
+    #[arg(long)]
+    pub no_hidden: bool,
+    // don't cross into other mounted filesystems
+    #[arg(long)]
+    pub one_file_system: bool,
+    // skip files larger than this, e.g. 500K, 10M, 1G
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_filesize: Option<u64>,
+    // look inside .tar, .tar.gz/.tgz and .zip files; members print as bundle.zip!/src/main.rs
+    #[arg(long)]
+    pub search_archives: bool,
+    // run COMMAND with each file's path and search its stdout instead, e.g. a pdftotext wrapper
+    #[arg(long, value_name = "COMMAND")]
+    pub pre: Option<String>,
+    // only use --pre on files matching one of these globs, e.g. --pre-glob '*.pdf'
+    #[arg(long, value_name = "GLOB", requires = "pre")]
+    pub pre_glob: Vec<String>,
+    // search comma separated records; quoted fields may hold commas and newlines
+    #[arg(long, conflicts_with = "tsv")]
+    pub csv: bool,
+    // search tab separated records
+    #[arg(long)]
+    pub tsv: bool,
+    // with --csv/--tsv: only match in these fields, by header name or 1-based index (--column
+    // is taken by the column number output)
+    #[arg(long, value_name = "NAME|INDEX", value_delimiter = ',')]
+    pub field: Vec<String>,
+    // with --csv/--tsv: print only these fields of a matching row
+    #[arg(long, value_name = "NAME|INDEX", value_delimiter = ',')]
+    pub print_fields: Vec<String>,
+    // parse each line (or the whole file) as JSON and match only the values at this path,
+    // e.g. '$.request.user' or 'items[*].name'; may be given more than once
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["csv", "tsv"])]
+    pub json_path: Vec<String>,
+    // with --json-path: print the matched values instead of the whole records
+    #[arg(long, requires = "json_path")]
+    pub json_values: bool,
+    // only search log lines timed at or after WHEN: 30m, 2h, 1d (ago), 2024-05-01 12:00 or RFC 3339
+    #[arg(long, value_name = "WHEN", value_parser = parse_when, conflicts_with_all = ["csv", "tsv", "json_path"])]
+    pub since: Option<DateTime<Utc>>,
+    // only search log lines timed at or before WHEN
+    #[arg(long, value_name = "WHEN", value_parser = parse_when, conflicts_with_all = ["csv", "tsv", "json_path"])]
+    pub until: Option<DateTime<Utc>>,
+    // strftime format of the time at the start of each line, e.g. '%d.%m.%Y %H:%M:%S'
+    #[arg(long, value_name = "FORMAT")]
+    pub time_format: Option<String>,
+    #[arg(long)]
+    pub verbose: bool,
+    // worker threads for walking and searching, defaults to one less than the CPU count
+    #[arg(short = 'j', long, value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    pub threads: Option<u16>,
+    // auto colours only when stdout is a terminal and NO_COLOR is unset
+    #[arg(long, value_enum, value_name = "WHEN", default_value = "auto")]
+    pub color: ColorChoice,
+    // e.g. --colors 'match:fg:yellow' --colors 'path:style:bold', applied after $DRINGREP_COLORS
+    #[arg(long = "colors", value_name = "SPEC")]
+    pub colors: Vec<String>,
+    // print a summary of files, bytes and matches after the search
+    #[arg(long)]
+    pub stats: bool,
+    // print results and the --stats summary as JSON lines
+    #[arg(long)]
+    pub json: bool,
+    // interactive search: re-runs as you type, Enter prints the selected path:line
+    #[arg(long, conflicts_with_all = ["json", "stats", "count", "files_with_matches", "files_without_match"])]
+    pub tui: bool,
+    // skip files that the nearest .dringrep-index shows can't match (see `dringrep index build`)
+    #[arg(long, requires = "recursive")]
+    pub index: bool,
+    // after searching, re-search files as they are created or changed and print the
+    // matches that appear (+) and disappear (-)
+    #[arg(long, conflicts_with_all = ["count", "files_with_matches", "files_without_match", "stats", "tui"])]
+    pub watch: bool,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum Command {
+    // manage the trigram index used by --index
+    Index {
+        #[command(subcommand)]
+        action: IndexCommand,
+    },
+    // scan DIR for committed credentials with the built-in rules; exits 1 on new findings
+    Secrets {
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+        // suppressions, one per line: path:GLOB, rule:ID or a regex for the line
+        // (defaults to DIR/.dringrep-allowlist)
+        #[arg(long, value_name = "FILE")]
+        allowlist: Option<PathBuf>,
+        // findings recorded here are known and not reported again
+        #[arg(long, value_name = "FILE")]
+        baseline: Option<PathBuf>,
+        // record every current finding in the baseline instead of reporting
+        #[arg(long, requires = "baseline")]
+        update_baseline: bool,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum IndexCommand {
+    // index every file under DIR into DIR/.dringrep-index; a rebuild only rereads changed files
+    Build { dir: PathBuf },
 }
-impl From<Args> for Config {
-    fn from(args: Args) -> Self {
+#[cfg(feature = "pcre")]
+fn build_pcre(q: &str, ignore_case: bool, budget: Option<usize>) -> Result<Pattern, String> {
+    let budget = budget.unwrap_or(DEFAULT_BACKTRACK_LIMIT);
+    PcreMatcher::new(q, ignore_case, budget)
+        .map(|pcre| Pattern::Pcre(Box::new(pcre)))
+        .map_err(|e| format!("Invalid regex `{}`: {}", q, e))
+}
+
+#[cfg(not(feature = "pcre"))]
+fn build_pcre(_q: &str, _ignore_case: bool, _budget: Option<usize>) -> Result<Pattern, String> {
+    Err("-P/--pcre needs dringrep built with `--features pcre`".to_string())
+}
+
+// plain bytes or a K/M/G suffix (powers of 1024)
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (digits, multiplier) = match s.chars().last().map(|c| c.to_ascii_uppercase()) {
+        Some('K') => (&s[..s.len() - 1], 1 << 10),
+        Some('M') => (&s[..s.len() - 1], 1 << 20),
+        Some('G') => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size `{}` (expected e.g. 4096, 500K, 10M, 1G)", s))
+}
+
+// errors are returned instead of exiting, so the TUI can rebuild a config on every keystroke
+impl TryFrom<Args> for Config {
+    type Error = String;
+
+    fn try_from(args: Args) -> Result<Self, String> {
         let ignore_case = args.ignore_case || env::var("IGNORE_CASE").is_ok();
 
         let file_path = match args.file_path {
@@ -83,7 +403,11 @@ impl From<Args> for Config {
             _ => "".to_string(),
         };
 
+        // an archive's own extension would filter out all of its members
         let file_extension = args.file_extension.or_else(|| {
+            if args.search_archives {
+                return None;
+            }
             Path::new(&file_path)
                 .extension()
                 .map(|ext| ext.to_string_lossy().to_string())
@@ -102,77 +426,438 @@ impl From<Args> for Config {
             }
         }
 
-        let pattern = if args.regex {
-            let q = if let Some(qs) = args.query.clone() {
-                qs
-            } else {
-                args.multiple.first().cloned().unwrap_or_else(|| {
-                    eprintln!("--regex requires a query string (use --query or --multiple).");
-                    std::process::exit(1);
-                })
-            };
+        // --query comes first, so it is pattern 0
+        let pattern_names: Vec<String> = args
+            .query
+            .iter()
+            .chain(args.multiple.iter())
+            .cloned()
+            .collect();
 
-            match RegexBuilder::new(&q).case_insensitive(ignore_case).build() {
-                Ok(re) => Pattern::Regex(re),
-                Err(e) => {
-                    eprintln!("Invalid regex `{}`: {}", q, e);
-                    process::exit(1);
-                }
-            }
+        let build_regex = |q: &str| -> Result<Regex, String> {
+            RegexBuilder::new(q)
+                .case_insensitive(ignore_case)
+                .build()
+                .map_err(|e| format!("Invalid regex `{}`: {}", q, e))
+        };
+
+        let mut pattern_names = pattern_names;
+        let pattern = if !args.preset.is_empty() {
+            pattern_names = args.preset.clone();
+            Pattern::Preset(Box::new(PresetMatcher::new(&args.preset)?))
+        } else if let Some(expr) = &args.expr {
+            let expr = QueryExpr::parse(expr, ignore_case)
+                .map_err(|e| format!("Invalid expression `{}`: {}", expr, e))?;
+            pattern_names = expr.leaf_names.clone();
+            Pattern::Expr(Box::new(expr))
+        } else if args.pcre {
+            build_pcre(
+                args.query.as_deref().unwrap_or_default(),
+                ignore_case,
+                args.pcre_budget,
+            )?
+        } else if let (Some(k), Some(q)) = (args.fuzzy, &args.query) {
+            let fuzzy =
+                FuzzyMatcher::new(q, k, ignore_case).map_err(|e| format!("Error: {}", e))?;
+            Pattern::Fuzzy(Box::new(fuzzy))
+        } else if args.regex && pattern_names.len() > 1 {
+            let regexes = pattern_names
+                .iter()
+                .map(|q| build_regex(q))
+                .collect::<Result<Vec<Regex>, String>>()?;
+            let set = RegexSetBuilder::new(&pattern_names)
+                .case_insensitive(ignore_case)
+                .build()
+                .map_err(|e| format!("Invalid regex set: {}", e))?;
+            Pattern::MultipleRegex { set, regexes }
+        } else if args.regex {
+            let q = pattern_names
+                .first()
+                .ok_or("--regex requires a query string (use --query or --multiple).")?;
+            Pattern::Regex(build_regex(q)?)
         } else if !args.multiple.is_empty() {
-            let ac = build_ac(&args.multiple, ignore_case);
+            let ac = build_ac(&pattern_names, ignore_case);
             Pattern::MultipleLiteral {
                 pattern: ac,
                 case_insensitive: ignore_case,
             }
         } else if let Some(q) = args.query {
-            let ac = build_ac(&vec![q], ignore_case);
+            let ac = build_ac(&[q], ignore_case);
             Pattern::Literal {
                 pattern: ac,
                 case_insensitive: ignore_case,
             }
         } else {
-            eprintln!(
+            return Err(
                 "Error: no query provided. Provide positional argument(1) for query <Q> or --multiple <Q>."
+                    .to_string(),
+            );
+        };
+
+        // DRINGREP_COLORS holds a default palette (e.g. one per terminal theme),
+        // separated by ';', and --colors overrides it
+        let env_specs = env::var("DRINGREP_COLORS").unwrap_or_default();
+        let mut colors = ColorSpecs::default();
+        for spec in env_specs
+            .split(';')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .chain(args.colors.iter().map(String::as_str))
+        {
+            colors.apply(spec).map_err(|e| format!("Error: {}", e))?;
+        }
+        let format = if args.vimgrep {
+            OutputFormat::Vimgrep
+        } else if args.emacs {
+            OutputFormat::Emacs
+        } else {
+            OutputFormat::Default
+        };
+        let color = args.color.enabled() && !args.json;
+
+        // a --file-scope expression decides for the whole file, so there are no lines to
+        // invert; -L lists the files it rejects
+        if args.file_scope && args.invert {
+            return Err(
+                "--file-scope can't be combined with --invert, use -L for the files that don't match"
+                    .to_string(),
+            );
+        }
+
+        let tailing = args.follow && !args.recursive;
+        if tailing
+            && (args.count || args.files_with_matches || args.files_without_match || args.watch)
+        {
+            return Err(
+                "--follow on a file streams lines as they are written, it can't be combined with -c, -l, -L or --watch"
+                    .to_string(),
+            );
+        }
+
+        // inverted searches (and -L) want the files without a match, which the index can't tell
+        let index = if args.index && !args.invert && !args.files_without_match {
+            let cwd = env::current_dir().map_err(|e| e.to_string())?;
+            Some(IndexFilter::open(
+                &cwd,
+                &pattern,
+                &pattern_names,
+                ignore_case,
+            )?)
+        } else {
+            None
+        };
+
+        let table = if args.csv || args.tsv {
+            let parse = |refs: &[String]| -> Result<Vec<FieldRef>, String> {
+                refs.iter().map(|r| FieldRef::parse(r)).collect()
+            };
+            Some(Table {
+                delimiter: if args.tsv { b'\t' } else { b',' },
+                fields: parse(&args.field)?,
+                print: parse(&args.print_fields)?,
+            })
+        } else if !args.field.is_empty() || !args.print_fields.is_empty() {
+            return Err("--field and --print-fields need --csv or --tsv".to_string());
+        } else {
+            None
+        };
+        if tailing && table.is_some() {
+            return Err(
+                "--follow on a file reads line by line, it can't be combined with --csv or --tsv"
+                    .to_string(),
             );
-            process::exit(1);
+        }
+
+        let json_path = if args.json_path.is_empty() {
+            None
+        } else {
+            Some(JsonQuery {
+                paths: args
+                    .json_path
+                    .iter()
+                    .map(|p| JsonPath::parse(p))
+                    .collect::<Result<_, _>>()?,
+                values: args.json_values,
+            })
+        };
+
+        let time_window = if args.since.is_some() || args.until.is_some() {
+            Some(TimeWindow {
+                since: args.since,
+                until: args.until,
+                format: args.time_format,
+            })
+        } else if args.time_format.is_some() {
+            return Err("--time-format needs --since or --until".to_string());
+        } else {
+            None
         };
 
-        Config {
+        let pre = match args.pre {
+            Some(command) => Some(Preprocessor::new(command, &args.pre_glob)?),
+            None => None,
+        };
+
+        Ok(Config {
             pattern,
+            pattern_names,
             file_path,
             ignore_case,
             invert: args.invert,
+            file_scope: args.file_scope,
             count: args.count,
             line_number: args.line_number,
             recursive: args.recursive,
-            file_name_if_matches: args.file_name_if_matches,
+            files_with_matches: args.files_with_matches,
+            files_without_match: args.files_without_match,
             file_extension,
-            highlight: args.highlight,
+            // only the default format is read by people; the others are parsed, and escape
+            // codes would end up inside JSON strings or shift the columns editors jump to
+            highlight: args.highlight && color && format == OutputFormat::Default,
+            only_matching: args.only_matching,
+            redact: args.redact,
+            column: args.column,
+            byte_offset: args.byte_offset,
+            format,
+            // a followed file would repeat its name over every batch of new lines
+            heading: if tailing {
+                false
+            } else if args.heading {
+                true
+            } else if args.no_heading {
+                false
+            } else {
+                io::stdout().is_terminal()
+            },
+            path_style: args.path_style,
+            null: args.null,
+            max_depth: args.max_depth,
+            follow: args.follow,
+            from_start: args.from_start,
+            hidden: !args.no_hidden,
+            one_file_system: args.one_file_system,
+            max_filesize: args.max_filesize,
+            search_archives: args.search_archives,
+            pre,
+            table,
+            json_path,
+            time_window,
+            verbose: args.verbose,
+            threads: args
+                .threads
+                .map(usize::from)
+                .unwrap_or_else(|| num_cpus::get().saturating_sub(1).max(1)),
+            color,
+            colors,
+            stats: args.stats.then(Stats::default),
+            json: args.json,
+            index,
+            watch: args.watch,
+        })
+    }
+}
+// a unit of work; `on_panic` gets the panic message if `run` panics, so the caller can
+// report it (e.g. as a FileResult::Error) instead of losing the work silently
+pub struct Job {
+    run: Box<dyn FnOnce() + Send + 'static>,
+    on_panic: Option<Box<dyn FnOnce(String) + Send + 'static>>,
+}
+
+// shared flag to stop a search early; queued jobs are dropped without running once it is set
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// per-worker counters, readable while the pool runs and after it is dropped
+#[derive(Default)]
+pub struct WorkerStats {
+    pub jobs: AtomicU64,
+    pub busy_nanos: AtomicU64,
+    pub panics: AtomicU64,
+}
+
+// state shared by the pool, its workers and every PoolHandle
+struct Shared {
+    injector: Injector<Job>,
+    stealers: Vec<Stealer<Job>>,
+    // jobs queued or running; workers only stop once this is 0 and the pool is closing
+    pending: AtomicUsize,
+    running: AtomicUsize,
+    closing: AtomicBool,
+    cancel: CancelToken,
+}
+
+thread_local! {
+    // the local deque of the pool worker running on this thread, tagged with its pool
+    static LOCAL: RefCell<Option<(usize, deque::Worker<Job>)>> = const { RefCell::new(None) };
+}
+
+impl Shared {
+    fn id(self: &Arc<Self>) -> usize {
+        Arc::as_ptr(self) as usize
+    }
+
+    // jobs submitted from one of our own workers go on its local deque (depth first, good
+    // locality); anything else goes through the injector
+    fn push(self: &Arc<Self>, job: Job) {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        let id = self.id();
+        let job = LOCAL.with_borrow(|local| match local {
+            Some((pool, deque)) if *pool == id => {
+                deque.push(job);
+                None
+            }
+            _ => Some(job),
+        });
+        if let Some(job) = job {
+            self.injector.push(job);
         }
     }
+
+    fn find_job(&self, local: &deque::Worker<Job>) -> Option<Job> {
+        local.pop().or_else(|| {
+            std::iter::repeat_with(|| {
+                self.injector
+                    .steal_batch_and_pop(local)
+                    .or_else(|| self.stealers.iter().map(|s| s.steal()).collect())
+            })
+            .find(|s| !s.is_retry())
+            .and_then(|s| s.success())
+        })
+    }
+}
+
+// decrements `pending` once the job is done with, run or not
+struct PendingGuard<'a>(&'a AtomicUsize);
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+// a cloneable way for running jobs to submit more work to the pool that runs them
+#[derive(Clone)]
+pub struct PoolHandle {
+    shared: Arc<Shared>,
+}
+
+impl PoolHandle {
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared.push(Job {
+            run: Box::new(f),
+            on_panic: None,
+        });
+    }
+
+    pub fn execute_reporting<F, P>(&self, f: F, on_panic: P)
+    where
+        F: FnOnce() + Send + 'static,
+        P: FnOnce(String) + Send + 'static,
+    {
+        self.shared.push(Job {
+            run: Box::new(f),
+            on_panic: Some(Box::new(on_panic)),
+        });
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.shared.cancel.is_cancelled()
+    }
 }
+
+// work-stealing pool: every worker owns a deque and steals from the injector or its
+// siblings when it runs dry, so jobs that fan out (like directory reads) spread evenly
 pub struct ThreadPool {
     pub workers: Vec<Worker>,
-    pub sender: Option<Sender<Job>>,
+    shared: Arc<Shared>,
 }
 
 pub struct Worker {
     pub id: usize,
     pub thread: Option<thread::JoinHandle<()>>,
+    pub stats: Arc<WorkerStats>,
 }
 
 impl Worker {
-    pub fn new(id: usize, receiver: Receiver<Job>, counter: Arc<Mutex<usize>>) -> Self {
+    fn new(id: usize, local: deque::Worker<Job>, shared: Arc<Shared>) -> Self {
+        let stats = Arc::new(WorkerStats::default());
+        let worker_stats = Arc::clone(&stats);
+
         let thread = thread::spawn(move || {
+            let pool_id = shared.id();
+            LOCAL.set(Some((pool_id, local)));
+            let backoff = Backoff::new();
+
             loop {
-                match receiver.recv() {
-                    Ok(job) => {
-                        job();
-                        let mut count = counter.lock().unwrap();
-                        *count += 1;
+                let job = LOCAL.with_borrow(|l| shared.find_job(&l.as_ref().unwrap().1));
+                let Some(Job { run, on_panic }) = job else {
+                    if shared.closing.load(Ordering::SeqCst)
+                        && shared.pending.load(Ordering::SeqCst) == 0
+                    {
+                        break;
+                    }
+                    if backoff.is_completed() {
+                        thread::park_timeout(Duration::from_millis(1));
+                    } else {
+                        backoff.snooze();
+                    }
+                    continue;
+                };
+
+                let _guard = PendingGuard(&shared.pending);
+                backoff.reset();
+                if shared.cancel.is_cancelled() {
+                    continue;
+                }
+
+                // a panicking job must not take the worker down with it: the panic is
+                // caught, reported, and the worker goes back to its loop as if freshly spawned
+                shared.running.fetch_add(1, Ordering::SeqCst);
+                let started = Instant::now();
+                let result = panic::catch_unwind(AssertUnwindSafe(run));
+                shared.running.fetch_sub(1, Ordering::SeqCst);
+
+                worker_stats.jobs.fetch_add(1, Ordering::Relaxed);
+                worker_stats
+                    .busy_nanos
+                    .fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+
+                if let Err(payload) = result {
+                    worker_stats.panics.fetch_add(1, Ordering::Relaxed);
+                    let message = panic_message(payload.as_ref());
+                    match on_panic {
+                        Some(report) => report(message),
+                        None => {
+                            eprintln!("worker {} recovered from a panicking job: {}", id, message)
+                        }
                     }
-                    Err(_) => break,
                 }
             }
         });
@@ -180,57 +865,200 @@ impl Worker {
         Worker {
             id,
             thread: Some(thread),
+            stats,
         }
     }
 }
 impl ThreadPool {
-    pub fn new(size: usize, counter: Arc<Mutex<usize>>) -> Self {
-        let mut workers = Vec::with_capacity(size);
+    pub fn new(size: usize) -> Self {
+        ThreadPool::with_cancel_token(size, CancelToken::new())
+    }
 
-        let (sender, receiver) = unbounded::<Job>();
+    // share `cancel` with whoever needs to stop this pool's work early
+    pub fn with_cancel_token(size: usize, cancel: CancelToken) -> Self {
+        let locals: Vec<deque::Worker<Job>> =
+            (0..size).map(|_| deque::Worker::new_lifo()).collect();
 
-        for id in 0..size {
-            let counter_clone = Arc::clone(&counter);
-            let rec_clone = receiver.clone();
-            workers.push(Worker::new(id as usize, rec_clone, counter_clone));
-        }
+        let shared = Arc::new(Shared {
+            injector: Injector::new(),
+            stealers: locals.iter().map(|l| l.stealer()).collect(),
+            pending: AtomicUsize::new(0),
+            running: AtomicUsize::new(0),
+            closing: AtomicBool::new(false),
+            cancel,
+        });
 
-        ThreadPool {
-            workers,
-            sender: Some(sender),
+        let workers = locals
+            .into_iter()
+            .enumerate()
+            .map(|(id, local)| Worker::new(id, local, Arc::clone(&shared)))
+            .collect();
+
+        ThreadPool { workers, shared }
+    }
+
+    pub fn handle(&self) -> PoolHandle {
+        PoolHandle {
+            shared: Arc::clone(&self.shared),
         }
     }
+
+    pub fn cancel_token(&self) -> CancelToken {
+        self.shared.cancel.clone()
+    }
+
+    // jobs waiting to run, not counting the ones currently running
+    pub fn queue_depth(&self) -> usize {
+        let pending = self.shared.pending.load(Ordering::SeqCst);
+        pending.saturating_sub(self.shared.running.load(Ordering::SeqCst))
+    }
+
+    pub fn worker_stats(&self) -> Vec<Arc<WorkerStats>> {
+        self.workers.iter().map(|w| Arc::clone(&w.stats)).collect()
+    }
 }
+// waits for every queued job, including jobs queued by other jobs, then joins the workers
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        self.sender.take();
+        self.shared.closing.store(true, Ordering::SeqCst);
         for worker in &mut self.workers {
             if let Some(t) = worker.thread.take() {
-                t.join().unwrap();
+                t.thread().unpark();
+                if t.join().is_err() {
+                    eprintln!("worker {} exited abnormally", worker.id);
+                }
             }
         }
     }
 }
 
-pub type Job = Box<dyn FnOnce() + Send + 'static>;
-
 impl ThreadPool {
     pub fn execute<F>(&self, f: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        let job = Box::new(f);
-
-        if let Some(sender) = &self.sender {
-            sender.send(job).expect("Worker thread has shut down");
-        } else {
+        if self.shared.closing.load(Ordering::SeqCst) {
             panic!("ThreadPool has been shut down");
         }
+        self.handle().execute(f);
+    }
+}
+
+// a hit inside a line: byte range plus the index of the pattern that produced it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub pattern: usize,
+}
+
+// one selected line, with enough position info for an editor to jump to the hit
+pub struct LineMatch<'a> {
+    // 1-based
+    pub line_number: usize,
+    // 1-based, counted in chars, of the first match on the line (1 for inverted lines)
+    pub column: usize,
+    // absolute offset in the file of the first match (of the line start for inverted lines)
+    pub byte_offset: usize,
+    // byte ranges of every hit in the unhighlighted line, empty for inverted lines
+    pub spans: Vec<Span>,
+    pub text: Cow<'a, str>,
+}
+
+impl LineMatch<'_> {
+    pub fn into_owned(self) -> LineMatch<'static> {
+        LineMatch {
+            line_number: self.line_number,
+            column: self.column,
+            byte_offset: self.byte_offset,
+            spans: self.spans,
+            text: Cow::Owned(self.text.into_owned()),
+        }
     }
 }
 
 pub enum FileResult {
-    Match(String, Vec<(usize, String)>),
+    Match(PathBuf, Vec<LineMatch<'static>>),
+    // a path printed on its own by -l / -L
+    Listed(PathBuf),
     Skip,
     Error(String),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn panicking_jobs_are_isolated() {
+        let pool = ThreadPool::new(2);
+        let stats = pool.worker_stats();
+        let (tx, rx) = mpsc::channel();
+        let (report_tx, report_rx) = mpsc::channel();
+
+        for i in 0..40 {
+            let tx = tx.clone();
+            if i == 7 {
+                pool.execute(move || panic!("job {} failed", i));
+            } else if i == 23 {
+                let report_tx = report_tx.clone();
+                pool.handle().execute_reporting(
+                    move || panic!("job {} failed", i),
+                    move |msg| report_tx.send(msg).unwrap(),
+                );
+            } else {
+                // every job also queues one from inside the pool
+                let handle = pool.handle();
+                pool.execute(move || {
+                    let nested = tx.clone();
+                    handle.execute(move || nested.send(i + 100).unwrap());
+                    tx.send(i).unwrap();
+                });
+            }
+        }
+        drop((tx, report_tx));
+
+        // dropping the pool waits for every job; a hang fails the test instead of blocking it
+        let (done_tx, done_rx) = mpsc::channel();
+        thread::spawn(move || {
+            drop(pool);
+            done_tx.send(()).unwrap();
+        });
+        done_rx
+            .recv_timeout(Duration::from_secs(10))
+            .expect("pool didn't shut down");
+
+        let mut results: Vec<usize> = rx.iter().collect();
+        results.sort_unstable();
+        let mut expected: Vec<usize> = (0..40)
+            .filter(|i| *i != 7 && *i != 23)
+            .flat_map(|i| [i, i + 100])
+            .collect();
+        expected.sort_unstable();
+        assert_eq!(results, expected);
+        assert_eq!(report_rx.iter().collect::<Vec<_>>(), ["job 23 failed"]);
+
+        let total = |f: fn(&WorkerStats) -> &AtomicU64| -> u64 {
+            stats.iter().map(|w| f(w).load(Ordering::Relaxed)).sum()
+        };
+        assert_eq!(total(|w| &w.panics), 2);
+        assert_eq!(total(|w| &w.jobs), 40 + 38);
+    }
+
+    #[test]
+    fn parse_size_suffixes() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("500k"), Ok(500 * 1024));
+        assert_eq!(parse_size(" 10M "), Ok(10 * 1024 * 1024));
+        assert_eq!(parse_size("1G"), Ok(1 << 30));
+    }
+
+    #[test]
+    fn parse_size_rejects_overflow_and_junk() {
+        assert!(parse_size("99999999999999G").is_err());
+        assert!(parse_size("18446744073709551616").is_err());
+        assert!(parse_size("10T").is_err());
+        assert!(parse_size("").is_err());
+    }
+}
@@ -1,38 +1,120 @@
-use colored::Colorize;
+use serde_json::json;
 
-extern crate num_cpus;
-
-use crate::{Config, FileResult, search};
+use crate::{
+    Config, FileResult, LineMatch, OutputFormat, Preprocessor, SkipReason, Stats, has_match,
+    search, search_chunk, search_json,
+};
 use crate::{ThreadPool, count_matches};
 use std::fs::{self, File};
 
-use std::io::SeekFrom;
-
-use std::io::{Read, Seek};
-use std::sync::{Arc, Mutex};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use std::sync::mpsc;
 
-use walkdir::DirEntry;
+use crate::archive::{archive_kind, search_archive};
+use crate::walk::Entry;
 
 pub fn print_results(rx: mpsc::Receiver<FileResult>, config: Arc<Config>) {
+    let mut first_block = true;
     for file_response in rx {
         match file_response {
-            FileResult::Match(n, v) => {
+            FileResult::Match(path, v) => {
                 let config = Arc::clone(&config);
+                let n = config.display_path(&path);
                 if config.count {
                     let count_matches = count_matches(&v);
                     println!("Number of matched lines found: {count_matches:?}");
                 }
 
-                if config.file_name_if_matches && v.len() > 0 {
-                    println!("File name: {}", config.file_path)
+                if config.json {
+                    for m in &v {
+                        let line = json!({
+                            "type": "match",
+                            "path": n,
+                            "line_number": m.line_number,
+                            "column": m.column,
+                            "byte_offset": m.byte_offset,
+                            "patterns": pattern_ids(m)
+                                .into_iter()
+                                .map(|i| json!({ "index": i, "pattern": config.pattern_names.get(i) }))
+                                .collect::<Vec<_>>(),
+                            "text": m.text,
+                        });
+                        println!("{}", line);
+                    }
+                    continue;
                 }
-                for (key, value) in &v {
-                    let config = Arc::clone(&config);
-                    print_each_result(config, &n, (*key, value));
+
+                match config.format {
+                    OutputFormat::Default if config.heading => {
+                        // blank line between files, not before the first one
+                        if !first_block {
+                            println!();
+                        }
+                        first_block = false;
+                        print_file_block(&config, &n, &v);
+                    }
+                    OutputFormat::Default => {
+                        for m in &v {
+                            let config = Arc::clone(&config);
+                            print_each_result(config, &n, m);
+                        }
+                    }
+                    OutputFormat::Vimgrep => {
+                        for m in &v {
+                            // inverted lines have no spans but still get one entry, and
+                            // with -o every match already is its own entry; a --csv row is not the
+                            // file's text, so only its first hit has a position
+                            let columns: Vec<usize> = if m.spans.is_empty()
+                                || config.only_matching
+                                || config.table.is_some()
+                            {
+                                vec![m.column]
+                            } else {
+                                m.spans
+                                    .iter()
+                                    .map(|s| m.text[..s.start].chars().count() + 1)
+                                    .collect()
+                            };
+                            for column in columns {
+                                println!(
+                                    "{}{}{}:{}:{}",
+                                    n,
+                                    config.path_terminator(":"),
+                                    m.line_number,
+                                    column,
+                                    m.text
+                                );
+                            }
+                        }
+                    }
+                    OutputFormat::Emacs => {
+                        for m in &v {
+                            println!(
+                                "{}{}{}:{}: {}",
+                                n,
+                                config.path_terminator(":"),
+                                m.line_number,
+                                m.column,
+                                m.text
+                            );
+                        }
+                    }
                 }
             }
+            FileResult::Listed(path) if config.json => {
+                println!(
+                    "{}",
+                    json!({ "type": "path", "path": config.display_path(&path) })
+                )
+            }
+            FileResult::Listed(path) => print!(
+                "{}{}",
+                config.display_path(&path),
+                config.path_terminator("\n")
+            ),
             FileResult::Error(e) => eprintln!("Error: {}", e),
             FileResult::Skip => {}
         }
@@ -42,130 +124,399 @@ pub fn print_results(rx: mpsc::Receiver<FileResult>, config: Arc<Config>) {
 pub fn normalize_extension(ext: &str) -> &str {
     ext.strip_prefix('.').unwrap_or(ext)
 }
+
+pub fn record_skip(config: &Config, path: &Path, reason: SkipReason) -> FileResult {
+    if config.verbose {
+        eprintln!("skipped {} ({})", path.display(), reason.label());
+    }
+    if let Some(stats) = &config.stats {
+        stats.skipped(reason);
+    }
+    FileResult::Skip
+}
+
+fn too_large(entry: &Entry, config: &Config) -> bool {
+    let Some(max) = config.max_filesize else {
+        return false;
+    };
+    entry.metadata().map(|m| m.len() > max).unwrap_or(false)
+}
+
+pub(crate) fn extension_matches(entry: &Entry, config: &Config) -> bool {
+    let Some(config_ext) = &config.file_extension else {
+        return true;
+    };
+    let curr_ext = entry
+        .path()
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(normalize_extension);
+
+    curr_ext == Some(normalize_extension(config_ext))
+}
+
+// --index: unchanged and missing the query's trigrams
+// (the index knows the raw bytes, not what --pre makes of them, --csv fields unquoted or
+// JSON strings unescaped)
+fn ruled_out(entry: &Entry, config: &Config) -> bool {
+    config
+        .index
+        .as_ref()
+        .is_some_and(|index| index.rules_out(entry.path()))
+        && preprocessor(entry, config).is_none()
+        && config.table.is_none()
+        && config.json_path.is_none()
+}
+
+fn preprocessor<'a>(entry: &Entry, config: &'a Config) -> Option<&'a Preprocessor> {
+    config
+        .pre
+        .as_ref()
+        .filter(|pre| pre.applies_to(entry.path()))
+}
+
+// -l / -L: answer "does this file match?" and stop reading at the first hit
+fn list_file(entry: &Entry, config: &Config) -> FileResult {
+    if let Some(stats) = &config.stats {
+        Stats::add(&stats.files_considered, 1);
+    }
+    if !extension_matches(entry, config) {
+        return record_skip(config, entry.path(), SkipReason::Filter);
+    }
+    if too_large(entry, config) {
+        return record_skip(config, entry.path(), SkipReason::TooLarge);
+    }
+    if ruled_out(entry, config) {
+        return record_skip(config, entry.path(), SkipReason::Indexed);
+    }
+
+    if let Some(pre) = preprocessor(entry, config) {
+        return match pre.run(entry.path()) {
+            Ok(output) => search_contents(config, entry.path().to_path_buf(), &output),
+            Err(e) => FileResult::Error(e),
+        };
+    }
+    if config.json_path.is_some() {
+        return match fs::read(entry.path()) {
+            Ok(bytes) => search_contents(config, entry.path().to_path_buf(), &bytes),
+            Err(_) => record_skip(config, entry.path(), SkipReason::Error),
+        };
+    }
+
+    let file = match File::open(entry.path()) {
+        Ok(f) => f,
+        Err(_) => return record_skip(config, entry.path(), SkipReason::Error),
+    };
+
+    listed(
+        config,
+        entry.path().to_path_buf(),
+        has_match(config, BufReader::new(file)),
+    )
+}
+
+fn listed(config: &Config, path: PathBuf, matched: io::Result<Option<bool>>) -> FileResult {
+    match matched {
+        Ok(Some(matched)) => {
+            if let Some(stats) = &config.stats {
+                Stats::add(&stats.files_searched, 1);
+            }
+            if matched == config.files_with_matches {
+                FileResult::Listed(path)
+            } else {
+                FileResult::Skip
+            }
+        }
+        Ok(None) => record_skip(config, &path, SkipReason::Binary),
+        Err(_) => record_skip(config, &path, SkipReason::Error),
+    }
+}
 pub fn process_batch(
-    batch: Vec<DirEntry>,
+    batch: Vec<Entry>,
     tx: mpsc::Sender<FileResult>,
     config: Arc<Config>,
     single_file: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    if single_file {
-        let entry = batch.first().unwrap();
-
-        let mut pool_size = num_cpus::get();
-        pool_size = pool_size.saturating_sub(1);
-        if pool_size == 0 {
-            pool_size = 1;
+    if config.lists_files() {
+        for entry in batch {
+            if let Some(kind) = archive_kind(&config, entry.path()) {
+                search_archive(&config, entry.path(), kind, &tx);
+                continue;
+            }
+            if let Err(send_err) = tx.send(list_file(&entry, &config)) {
+                eprintln!("failed to send result back to main: {:?}", send_err);
+            }
         }
-        let mut chunks = Vec::new();
+    } else if single_file
+        && !config.file_scope
+        && archive_kind(&config, batch.first().unwrap().path()).is_none()
+        && preprocessor(batch.first().unwrap(), &config).is_none()
+        && config.table.is_none()
+        && config.json_path.is_none()
+        && config.time_window.is_none()
+    {
+        // chunks can't evaluate --file-scope on their own, so that falls through to a whole-file search
+        // (an archive is searched member by member below, --pre output, --csv and --json-path
+        // records as a whole, and --since/--until need the time of the lines above a chunk)
+        let entry = batch.first().unwrap();
 
-        let file_counter = Arc::new(Mutex::new(0));
-        let thread_pool = ThreadPool::new(pool_size, file_counter);
+        let pool_size = config.threads;
+        let thread_pool = ThreadPool::new(pool_size);
+        let pool = thread_pool.handle();
 
         let metadata = fs::metadata(entry.path());
         let file_size_bytes = metadata.ok().unwrap().len();
-        let chunk_size = (file_size_bytes + pool_size as u64 - 1) / pool_size as u64;
+        if let Some(stats) = &config.stats {
+            Stats::add(&stats.files_considered, 1);
+            Stats::add(&stats.files_searched, 1);
+            Stats::add(&stats.bytes_read, file_size_bytes);
+        }
+        let chunk_size = file_size_bytes.div_ceil(pool_size as u64);
+
+        // chunks are read in order and extended to the end of their last line, so no line is
+        // split in two and every chunk knows the global line number and offset it starts at
+        let mut reader = BufReader::new(File::open(entry.path())?);
         let mut start = 0;
+        let mut first_line = 0;
+        // chunk results are gathered here and sent as one result, so the file is printed
+        // as a single block in line order
+        let (chunk_tx, chunk_rx) = mpsc::channel::<Vec<LineMatch>>();
 
-        for _ in 0..pool_size {
-            let end = std::cmp::min(start + chunk_size, file_size_bytes);
-            if start >= end {
-                break;
+        while start < file_size_bytes {
+            let length = std::cmp::min(chunk_size, file_size_bytes - start);
+            let mut buffer = vec![0; length as usize];
+            reader.read_exact(&mut buffer)?;
+            if !buffer.ends_with(b"\n") {
+                reader.read_until(b'\n', &mut buffer)?;
             }
-            chunks.push((start, end));
-            start = end;
-        }
 
-        for (start, end) in chunks {
-            // we are moving the cursor of the file whereever our start is
-            let mut f = File::open(&config.file_path)?;
-            f.seek(SeekFrom::Start(start))?;
+            let chunk_start = start as usize;
+            let chunk_first_line = first_line;
+            start += buffer.len() as u64;
+            first_line += buffer.iter().filter(|&&b| b == b'\n').count();
+
             let config = Arc::clone(&config);
-            let tx = tx.clone();
-            let length = end - start;
-            let mut buffer = vec![0; length as usize];
-            f.read_exact(&mut buffer)?;
+            let chunk_tx = chunk_tx.clone();
+            let error_tx = tx.clone();
+            let path = entry.path().to_path_buf();
 
-            thread_pool.execute(move || {
-                let file_contents = String::from_utf8_lossy(&buffer);
-                let temp = search(&config, &file_contents);
+            let on_panic = move |msg: String| {
+                let _ = error_tx.send(FileResult::Error(format!(
+                    "search of {} (from line {}) panicked: {}",
+                    path.display(),
+                    chunk_first_line + 1,
+                    msg
+                )));
+            };
+            pool.execute_reporting(
+                move || {
+                    let file_contents = String::from_utf8_lossy(&buffer);
+                    let temp = search_chunk(&config, &file_contents, chunk_first_line, chunk_start);
 
-                if !temp.is_empty() {
-                    let owned_temp: Vec<(usize, String)> = temp
-                        .into_iter()
-                        .map(|(idx, s)| (idx, s.to_string()))
-                        .collect();
+                    if !temp.is_empty() {
+                        let owned_temp: Vec<LineMatch> =
+                            temp.into_iter().map(LineMatch::into_owned).collect();
 
-                    if let Err(e) = tx.send(FileResult::Match(config.file_path.clone(), owned_temp))
-                    {
-                        eprintln!("failed to send chunk result: {:?}", e);
+                        if let Err(e) = chunk_tx.send(owned_temp) {
+                            eprintln!("failed to send chunk result: {:?}", e);
+                        }
                     }
-                }
-            })
+                },
+                on_panic,
+            )
+        }
+        drop(chunk_tx);
+        drop(thread_pool);
+
+        let mut matches: Vec<LineMatch> = chunk_rx.into_iter().flatten().collect();
+        if !matches.is_empty() {
+            matches.sort_by_key(|m| m.line_number);
+            if let Err(e) = tx.send(FileResult::Match(entry.path().to_path_buf(), matches)) {
+                eprintln!("failed to send result back to main: {:?}", e);
+            }
         }
     } else {
         for entry in batch {
+            // members are sent one by one, the archive itself is no result
+            if let Some(kind) = archive_kind(&config, entry.path()) {
+                search_archive(&config, entry.path(), kind, &tx);
+                continue;
+            }
             let res = (|| -> FileResult {
-                if !entry.file_type().is_file() {
-                    return FileResult::Skip;
+                if let Some(stats) = &config.stats {
+                    Stats::add(&stats.files_considered, 1);
+                }
+
+                // check the extension before reading so filtered files cost nothing
+                if !extension_matches(&entry, &config) {
+                    return record_skip(&config, entry.path(), SkipReason::Filter);
+                }
+                if too_large(&entry, &config) {
+                    return record_skip(&config, entry.path(), SkipReason::TooLarge);
+                }
+                if ruled_out(&entry, &config) {
+                    return record_skip(&config, entry.path(), SkipReason::Indexed);
                 }
 
                 let path = entry.path().to_path_buf();
-                let bytes = match fs::read(&path) {
-                    Ok(b) => b,
-                    _ => {
-                        return FileResult::Skip;
-                    }
+                let bytes = match preprocessor(&entry, &config) {
+                    Some(pre) => match pre.run(&path) {
+                        Ok(output) => output,
+                        Err(e) => return FileResult::Error(e),
+                    },
+                    None => match fs::read(&path) {
+                        Ok(b) => b,
+                        _ => {
+                            return record_skip(&config, &path, SkipReason::Error);
+                        }
+                    },
                 };
 
-                if std::str::from_utf8(&bytes).is_err() {
-                    return FileResult::Skip;
-                }
+                search_contents(&config, path, &bytes)
+            })();
+            if let Err(send_err) = tx.send(res) {
+                eprintln!("failed to send result back to main: {:?}", send_err);
+            }
+        }
+    }
 
-                let file_name = entry.file_name();
+    Ok(())
+}
 
-                if let Some(config_ext) = &config.file_extension {
-                    let config_ext = normalize_extension(&config_ext);
-                    let curr_ext = entry
-                        .path()
-                        .extension()
-                        .and_then(|ext| ext.to_str())
-                        .map(normalize_extension);
+// the part of a file search after reading: binary check, then search (or -l/-L)
+pub(crate) fn search_contents(config: &Config, path: PathBuf, bytes: &[u8]) -> FileResult {
+    // has_match counts the bytes itself and stops at the first hit
+    // (--json-path needs the file's name for its warnings, so it takes the long way)
+    if config.lists_files() && config.json_path.is_none() {
+        return listed(config, path, has_match(config, bytes));
+    }
 
-                    if curr_ext != Some(config_ext) {
-                        return FileResult::Skip;
-                    }
-                }
+    if let Some(stats) = &config.stats {
+        Stats::add(&stats.bytes_read, bytes.len() as u64);
+    }
 
-                let file_contents = String::from_utf8_lossy(&bytes);
+    let Ok(file_contents) = std::str::from_utf8(bytes) else {
+        return record_skip(config, &path, SkipReason::Binary);
+    };
 
-                let temp = search(&*config, &file_contents);
+    let temp = match &config.json_path {
+        Some(query) => search_json(config, query, &path, file_contents, 0, 0),
+        None => search(config, file_contents),
+    };
+    if config.lists_files() {
+        return listed(config, path, Ok(Some(!temp.is_empty())));
+    }
 
-                if temp.is_empty() {
-                    return FileResult::Skip;
-                }
+    if let Some(stats) = &config.stats {
+        Stats::add(&stats.files_searched, 1);
+    }
 
-                let owned_temp: Vec<(usize, String)> = temp
-                    .into_iter()
-                    .map(|(idx, s)| (idx, s.to_string()))
-                    .collect();
+    if temp.is_empty() {
+        return FileResult::Skip;
+    }
 
-                let file_name_owned = file_name.to_string_lossy().into_owned();
+    let owned_temp: Vec<LineMatch> = temp.into_iter().map(LineMatch::into_owned).collect();
 
-                FileResult::Match(file_name_owned, owned_temp)
-            })();
-            if let Err(send_err) = tx.send(res) {
-                eprintln!("failed to send result back to main: {:?}", send_err);
-            }
+    FileResult::Match(path, owned_temp)
+}
+
+// indexes of the patterns that hit this line, each once
+fn pattern_ids(m: &LineMatch) -> Vec<usize> {
+    let mut ids: Vec<usize> = m.spans.iter().map(|s| s.pattern).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    ids
+}
+
+// "[#0 foo, #2 ba+r] " in front of the line when several patterns are searched at once
+fn pattern_tag(config: &Config, m: &LineMatch) -> String {
+    if config.pattern_names.len() < 2 {
+        return String::new();
+    }
+    let tags: Vec<String> = pattern_ids(m)
+        .into_iter()
+        .map(|i| {
+            let name = config.pattern_names.get(i).map_or("", String::as_str);
+            config
+                .colors
+                .for_pattern(i)
+                .paint(&format!("#{} {}", i, name))
+                .to_string()
+        })
+        .collect();
+    if tags.is_empty() {
+        return String::new();
+    }
+    format!("[{}] ", tags.join(", "))
+}
+
+// --heading: the path once, then its lines. Every file arrives as one FileResult and is written
+// with a single locked write, so blocks from parallel workers never interleave
+pub fn print_file_block(config: &Config, name: &str, v: &[LineMatch]) {
+    let colors = &config.colors;
+    let sep = colors.separator.paint(":").to_string();
+
+    let mut block = format!(
+        "{}{}",
+        colors.path.paint(name),
+        config.path_terminator("\n")
+    );
+    for m in v {
+        let mut positions = vec![m.line_number];
+        if config.column {
+            positions.push(m.column);
+        }
+        if config.byte_offset {
+            positions.push(m.byte_offset);
         }
+        for value in positions {
+            block.push_str(&colors.line.paint(&value.to_string()).to_string());
+            block.push_str(&sep);
+        }
+        block.push_str(&pattern_tag(config, m));
+        block.push_str(&m.text);
+        block.push('\n');
     }
 
-    Ok(())
+    let mut stdout = io::stdout().lock();
+    if let Err(e) = stdout.write_all(block.as_bytes()) {
+        eprintln!("failed to write results: {}", e);
+    }
 }
 
-pub fn print_each_result(config: Arc<Config>, name: &str, v: (usize, &String)) {
+pub fn print_each_result(config: Arc<Config>, name: &str, m: &LineMatch) {
+    let colors = &config.colors;
+    let name = colors.path.paint(name);
+
+    let mut positions = Vec::new();
     if config.line_number {
-        println!("{} - line: {}, {}", name.green(), v.0, v.1);
-    } else {
-        println!("{}: {}", name.green(), v.1);
+        positions.push(("line", m.line_number));
+    }
+    if config.column {
+        positions.push(("column", m.column));
+    }
+    if config.byte_offset {
+        positions.push(("byte", m.byte_offset));
+    }
+
+    if positions.is_empty() {
+        let sep = colors.separator.paint(config.path_terminator(": "));
+        println!("{}{}{}{}", name, sep, pattern_tag(&config, m), m.text);
+        return;
+    }
+
+    let mut out = format!(
+        "{}{}",
+        name,
+        colors.separator.paint(config.path_terminator(" - "))
+    );
+    for (label, value) in positions {
+        out.push_str(&format!(
+            "{}{}{}",
+            colors.separator.paint(&format!("{}: ", label)),
+            colors.line.paint(&value.to_string()),
+            colors.separator.paint(", ")
+        ));
     }
+    println!("{}{}{}", out, pattern_tag(&config, m), m.text);
 }
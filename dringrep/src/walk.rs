@@ -0,0 +1,242 @@
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc;
+
+use crate::utils::record_skip;
+use crate::{Config, FileResult, PoolHandle, SkipReason, process_batch};
+
+// files are handed to the search in batches so one job isn't spent on a single tiny file,
+// but small enough that idle workers can still steal part of a big directory
+const BATCH_SIZE: usize = 32;
+
+// a file found by the walk
+pub struct Entry {
+    pub path: PathBuf,
+    // 0 for the starting path, 1 for its children, ...
+    pub depth: usize,
+}
+
+impl Entry {
+    pub fn new(path: PathBuf, depth: usize) -> Self {
+        Entry { path, depth }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // follows symlinks, like the --follow walk does
+    pub fn metadata(&self) -> io::Result<Metadata> {
+        fs::metadata(&self.path)
+    }
+}
+
+struct Dir {
+    path: PathBuf,
+    depth: usize,
+    // canonical paths of the directories above this one, only tracked with --follow
+    ancestors: Arc<Vec<PathBuf>>,
+    // device of the starting directory, only tracked with --one-file-system
+    device: Option<u64>,
+}
+
+// dotfiles and dot-directories; the starting path is never hidden
+pub fn is_hidden(path: &Path, depth: usize) -> bool {
+    depth > 0
+        && path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+#[cfg(unix)]
+fn device_of(metadata: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.dev())
+}
+
+#[cfg(not(unix))]
+fn device_of(_metadata: &Metadata) -> Option<u64> {
+    None
+}
+
+// Every directory is read by its own pool job, and reading it queues jobs for its
+// subdirectories and batches of its files. Workers steal whatever is queued, so
+// discovering and searching happen together on all threads.
+pub fn walk_parallel(
+    root: PathBuf,
+    config: Arc<Config>,
+    pool: PoolHandle,
+    tx: mpsc::Sender<FileResult>,
+) {
+    let root_meta = match fs::metadata(&root) {
+        Ok(m) => m,
+        Err(_) => {
+            record_skip(&config, &root, SkipReason::Error);
+            return;
+        }
+    };
+
+    let ancestors = if config.follow {
+        Arc::new(vec![
+            fs::canonicalize(&root).unwrap_or_else(|_| root.clone()),
+        ])
+    } else {
+        Arc::new(Vec::new())
+    };
+    let dir = Dir {
+        path: root,
+        depth: 0,
+        ancestors,
+        device: if config.one_file_system {
+            device_of(&root_meta)
+        } else {
+            None
+        },
+    };
+
+    let handle = pool.clone();
+    pool.execute(move || read_dir(dir, config, handle, tx));
+}
+
+fn read_dir(dir: Dir, config: Arc<Config>, pool: PoolHandle, tx: mpsc::Sender<FileResult>) {
+    let depth = dir.depth + 1;
+    if config.max_depth.is_some_and(|max| depth > max) {
+        record_skip(&config, &dir.path, SkipReason::TooDeep);
+        return;
+    }
+
+    let entries = match fs::read_dir(&dir.path) {
+        Ok(entries) => entries,
+        Err(_) => {
+            record_skip(&config, &dir.path, SkipReason::Error);
+            return;
+        }
+    };
+
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+
+    for entry in entries {
+        if pool.is_cancelled() {
+            return;
+        }
+        let entry = match entry {
+            Ok(e) => e,
+            Err(_) => {
+                record_skip(&config, &dir.path, SkipReason::Error);
+                continue;
+            }
+        };
+        let path = entry.path();
+
+        if !config.hidden && is_hidden(&path, depth) {
+            record_skip(&config, &path, SkipReason::Hidden);
+            continue;
+        }
+
+        let mut file_type = match entry.file_type() {
+            Ok(t) => t,
+            Err(_) => {
+                record_skip(&config, &path, SkipReason::Error);
+                continue;
+            }
+        };
+        // without --follow symlinks are not searched
+        if file_type.is_symlink() {
+            if !config.follow {
+                record_skip(&config, &path, SkipReason::Symlink);
+                continue;
+            }
+            match fs::metadata(&path) {
+                Ok(m) => file_type = m.file_type(),
+                Err(_) => {
+                    record_skip(&config, &path, SkipReason::Error);
+                    continue;
+                }
+            }
+        }
+
+        if file_type.is_file() {
+            batch.push(Entry::new(path, depth));
+            if batch.len() == BATCH_SIZE {
+                submit_batch(std::mem::take(&mut batch), &config, &pool, &tx);
+            }
+        } else if file_type.is_dir()
+            && let Some(child) = child_dir(&dir, path, depth, &config)
+        {
+            let config = Arc::clone(&config);
+            let tx = tx.clone();
+            let handle = pool.clone();
+            pool.execute(move || read_dir(child, config, handle, tx));
+        }
+    }
+
+    if !batch.is_empty() {
+        submit_batch(batch, &config, &pool, &tx);
+    }
+}
+
+// None when the directory must not be entered: another filesystem, its entries past
+// --max-depth or a symlink loop
+fn child_dir(parent: &Dir, path: PathBuf, depth: usize, config: &Config) -> Option<Dir> {
+    if let Some(device) = parent.device {
+        let child_device = fs::metadata(&path).ok().and_then(|m| device_of(&m));
+        if child_device != Some(device) {
+            record_skip(config, &path, SkipReason::OtherFilesystem);
+            return None;
+        }
+    }
+    if config.max_depth.is_some_and(|max| depth + 1 > max) {
+        record_skip(config, &path, SkipReason::TooDeep);
+        return None;
+    }
+
+    let ancestors = if config.follow {
+        let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        if parent.ancestors.contains(&canonical) {
+            record_skip(config, &path, SkipReason::Loop);
+            return None;
+        }
+        let mut ancestors = (*parent.ancestors).clone();
+        ancestors.push(canonical);
+        Arc::new(ancestors)
+    } else {
+        Arc::clone(&parent.ancestors)
+    };
+
+    Some(Dir {
+        path,
+        depth,
+        ancestors,
+        device: parent.device,
+    })
+}
+
+fn submit_batch(
+    batch: Vec<Entry>,
+    config: &Arc<Config>,
+    pool: &PoolHandle,
+    tx: &mpsc::Sender<FileResult>,
+) {
+    let config = Arc::clone(config);
+    let error_tx = tx.clone();
+    let tx = tx.clone();
+    // named up front so a panic in the batch can still say which files were lost
+    let first = batch[0].path.display().to_string();
+    let count = batch.len();
+
+    pool.execute_reporting(
+        move || {
+            if let Err(e) = process_batch(batch, tx, config, false) {
+                eprintln!("Error processing batch: {}", e);
+            }
+        },
+        move |msg| {
+            let _ = error_tx.send(FileResult::Error(format!(
+                "search of {} files starting at {} panicked: {}",
+                count, first, msg
+            )));
+        },
+    );
+}
@@ -0,0 +1,278 @@
+/*
+--watch: after the normal search, keep watching the searched paths and re-search only the
+files that are created or change. Each re-search is compared with the file's previous
+matches and the difference is printed as a feed:
+
+    + src/gen/api.rs:12: let token = "hunter2";
+    - config/dev.toml:3: debug = true
+
+The same rules as the walk apply (--no-hidden, --max-depth, --file-extension,
+--max-filesize), because changed files go through process_batch like any other.
+With --search-archives a changed archive is compared member by member.
+*/
+
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use colored::Colorize;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use serde_json::json;
+
+use crate::{Config, Entry, FileResult, LineMatch, process_batch};
+
+// editors save in several steps (truncate, write, rename); events this close together are
+// handled as one change
+const SETTLE: Duration = Duration::from_millis(100);
+
+// every file's current matches as (line number, text)
+pub type Snapshot = HashMap<PathBuf, Vec<(usize, String)>>;
+
+fn snapshot_of(lines: &[LineMatch]) -> Vec<(usize, String)> {
+    lines
+        .iter()
+        .map(|m| (m.line_number, m.text.to_string()))
+        .collect()
+}
+
+// hands every result on to the printer while remembering the matches of each file
+pub fn tee_results(rx: Receiver<FileResult>) -> (Receiver<FileResult>, JoinHandle<Snapshot>) {
+    let (tx, print_rx) = mpsc::channel();
+    let handle = thread::spawn(move || {
+        let mut snapshot = Snapshot::new();
+        for result in rx {
+            if let FileResult::Match(path, lines) = &result {
+                let path = std::path::absolute(path).unwrap_or_else(|_| path.clone());
+                snapshot.insert(path, snapshot_of(lines));
+            }
+            if tx.send(result).is_err() {
+                break;
+            }
+        }
+        snapshot
+    });
+    (print_rx, handle)
+}
+
+pub fn watch(
+    config: Arc<Config>,
+    root: PathBuf,
+    mut snapshot: Snapshot,
+) -> Result<(), Box<dyn Error>> {
+    let root = std::path::absolute(&root)?;
+    let (tx, rx) = mpsc::channel::<notify::Result<Event>>();
+    let mut watcher = notify::recommended_watcher(tx)?;
+
+    // a single file is watched through its directory, so it is still seen after an editor
+    // replaces it with a new file
+    let single_file = root.is_file();
+    if single_file {
+        let parent = root.parent().unwrap_or(Path::new("/"));
+        watcher.watch(parent, RecursiveMode::NonRecursive)?;
+    } else {
+        watcher.watch(&root, RecursiveMode::Recursive)?;
+    }
+    eprintln!("watching {} for changes", root.display());
+
+    loop {
+        let mut changed = BTreeSet::new();
+        collect_paths(rx.recv()?, &mut changed);
+        while let Ok(event) = rx.recv_timeout(SETTLE) {
+            collect_paths(event, &mut changed);
+        }
+
+        for path in changed {
+            if single_file && path != root {
+                continue;
+            }
+            rescan(&config, &root, &path, &mut snapshot);
+        }
+    }
+}
+
+fn collect_paths(event: notify::Result<Event>, changed: &mut BTreeSet<PathBuf>) {
+    match event {
+        // reading a file changes nothing
+        Ok(event) if matches!(event.kind, EventKind::Access(_)) => {}
+        Ok(event) => changed.extend(event.paths),
+        Err(e) => eprintln!("watch error: {}", e),
+    }
+}
+
+// the walk's rules for which paths are searched at all
+fn in_scope(config: &Config, root: &Path, path: &Path) -> Option<usize> {
+    let relative = path.strip_prefix(root).ok()?;
+    let depth = relative.components().count();
+    let hidden = relative
+        .components()
+        .any(|c| c.as_os_str().to_string_lossy().starts_with('.'));
+
+    if (!config.hidden && hidden) || config.max_depth.is_some_and(|max| depth > max) {
+        return None;
+    }
+    Some(depth)
+}
+
+fn rescan(config: &Arc<Config>, root: &Path, path: &Path, snapshot: &mut Snapshot) {
+    let Some(depth) = in_scope(config, root, path) else {
+        return;
+    };
+
+    // like the walk, links are only followed with --follow (or when named on the command line)
+    let metadata = if config.follow || path == root {
+        fs::metadata(path)
+    } else {
+        fs::symlink_metadata(path)
+    };
+    let current = match metadata {
+        Ok(m) if m.is_file() => search_file(config, path, depth),
+        // a directory moved into the tree arrives as one event, its files need a look too
+        Ok(m) if m.is_dir() => {
+            if let Ok(entries) = fs::read_dir(path) {
+                for entry in entries.flatten() {
+                    rescan(config, root, &entry.path(), snapshot);
+                }
+            }
+            return;
+        }
+        Ok(_) => return,
+        // gone: a removed directory takes the matches of everything below it along
+        Err(_) => {
+            let below: Vec<PathBuf> = snapshot
+                .keys()
+                .filter(|p| p.starts_with(path) && p.as_path() != path)
+                .cloned()
+                .collect();
+            for p in below {
+                report(
+                    config,
+                    &p,
+                    snapshot.remove(&p).unwrap_or_default(),
+                    Vec::new(),
+                );
+            }
+            Vec::new()
+        }
+    };
+
+    // the file itself, or with --search-archives every member it had before; a member that
+    // no longer matches (or no longer exists) loses its matches
+    let before: Vec<PathBuf> = snapshot
+        .keys()
+        .filter(|p| p.as_path() == path || is_member(path, p))
+        .filter(|p| !current.iter().any(|(c, _)| c == *p))
+        .cloned()
+        .collect();
+    for p in before {
+        report(
+            config,
+            &p,
+            snapshot.remove(&p).unwrap_or_default(),
+            Vec::new(),
+        );
+    }
+    for (p, lines) in current {
+        let previous = snapshot.remove(&p).unwrap_or_default();
+        if !lines.is_empty() {
+            snapshot.insert(p.clone(), lines.clone());
+        }
+        report(config, &p, previous, lines);
+    }
+}
+
+// `bundle.zip!/src/main.rs` is a member of `bundle.zip`
+fn is_member(archive: &Path, path: &Path) -> bool {
+    let prefix = format!("{}!/", archive.display());
+    path.to_string_lossy().starts_with(&prefix)
+}
+
+// the matches of every file the search reports: just `path`, or each archive member
+fn search_file(
+    config: &Arc<Config>,
+    path: &Path,
+    depth: usize,
+) -> Vec<(PathBuf, Vec<(usize, String)>)> {
+    let (tx, rx) = mpsc::channel();
+    let entry = Entry::new(path.to_path_buf(), depth);
+    if let Err(e) = process_batch(vec![entry], tx, Arc::clone(config), false) {
+        eprintln!("Error processing {}: {}", path.display(), e);
+    }
+    let mut found = Vec::new();
+    for result in rx {
+        match result {
+            FileResult::Match(p, lines) => found.push((p, snapshot_of(&lines))),
+            FileResult::Error(e) => eprintln!("Error: {}", e),
+            _ => {}
+        }
+    }
+    found
+}
+
+// matches are compared by text, so a line that only moved (because lines were added above it)
+// is neither added nor removed
+fn report(
+    config: &Config,
+    path: &Path,
+    previous: Vec<(usize, String)>,
+    current: Vec<(usize, String)>,
+) {
+    let mut unmatched: HashMap<&str, usize> = HashMap::new();
+    for (_, text) in &current {
+        *unmatched.entry(text).or_default() += 1;
+    }
+    let mut removed = Vec::new();
+    for m in &previous {
+        match unmatched.get_mut(m.1.as_str()) {
+            Some(n) if *n > 0 => *n -= 1,
+            _ => removed.push(m),
+        }
+    }
+
+    let mut unmatched: HashMap<&str, usize> = HashMap::new();
+    for (_, text) in &previous {
+        *unmatched.entry(text).or_default() += 1;
+    }
+    let mut added = Vec::new();
+    for m in &current {
+        match unmatched.get_mut(m.1.as_str()) {
+            Some(n) if *n > 0 => *n -= 1,
+            _ => added.push(m),
+        }
+    }
+
+    let name = config.display_path(path);
+    for (line_number, text) in removed {
+        print_change(config, false, &name, *line_number, text);
+    }
+    for (line_number, text) in added {
+        print_change(config, true, &name, *line_number, text);
+    }
+}
+
+fn print_change(config: &Config, added: bool, name: &str, line_number: usize, text: &str) {
+    if config.json {
+        let line = json!({
+            "type": if added { "added" } else { "removed" },
+            "path": name,
+            "line_number": line_number,
+            "text": text,
+        });
+        println!("{}", line);
+        return;
+    }
+
+    let sign = if added { "+".green() } else { "-".red() };
+    println!(
+        "{} {}{}{}: {}",
+        sign,
+        config.colors.path.paint(name),
+        config.path_terminator(":"),
+        line_number,
+        text
+    );
+}